@@ -1,5 +1,9 @@
 // This example shows how to compute keccak hashes in software. In
 // practice, using a keccak "pre-compile" will be more efficient.
+//
+// The CLI proves it as the `keccak_input` program: the public input is a
+// length-prefixed byte string, and the public output its 32-byte Keccak-256
+// digest, as computed by Ethereum.
 
 #![cfg_attr(target_arch = "riscv32", no_std, no_main)]
 #![allow(non_upper_case_globals)]
@@ -145,14 +149,15 @@ fn ethash_final(c: &mut Sha3) -> Vec<u8> {
     v
 }
 
-fn ethash(bytes: &[u8]) -> Vec<u8> {
+fn ethash(bytes: &[u8]) -> [u8; 32] {
     let mut c = sha3_init(32);
     sha3_update(&mut c, bytes);
-    ethash_final(&mut c)
+    ethash_final(&mut c).try_into().unwrap()
 }
 
+// Natively, the bytes to hash are the first line of stdin
 #[cfg(not(target_arch = "riscv32"))]
-fn public_input_native() -> Result<u32, String> {
+fn public_input_native() -> Result<Vec<u8>, String> {
     use std::io::{self, BufRead};
     let stdin = io::stdin();
     let mut lines = stdin.lock().lines();
@@ -162,26 +167,20 @@ fn public_input_native() -> Result<u32, String> {
         .ok_or("No input provided")?
         .map_err(|e| format!("Failed to read line: {}", e))?;
 
-    line.trim()
-        .parse()
-        .map_err(|e| format!("Failed to parse input as u32: {}", e))
+    Ok(line.into_bytes())
 }
 
 #[nexus_rt::main]
-#[cfg_attr(target_arch = "riscv32", nexus_rt::public_input(x))]
+#[cfg_attr(target_arch = "riscv32", nexus_rt::public_input(input))]
 #[cfg_attr(
     not(target_arch = "riscv32"),
-    nexus_rt::custom_input(x, public_input_native)
+    nexus_rt::custom_input(input, public_input_native)
 )]
-fn main(x: u32) {
-    let mut input: &[u8] = b"Hello, World!";
-    let mut result: Vec<u8> = Vec::new();
-    for i in 0..=x {
-        result = ethash(input);
-        input = result.as_slice();
-    }
-    for b in result {
-        print!("{b:x}");
+fn main(input: Vec<u8>) -> [u8; 32] {
+    let digest = ethash(&input);
+    for b in digest {
+        print!("{b:02x}");
     }
     println!();
+    digest
 }
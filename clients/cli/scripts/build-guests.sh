@@ -0,0 +1,71 @@
+#!/usr/bin/env bash
+# Build the bundled guest programs from the examples crate, copy their ELFs into
+# OUT_DIR (default: assets) and record them in OUT_DIR/manifest.json, with hashes of
+# their sources and the toolchain that built them.
+#
+#   scripts/build-guests.sh [OUT_DIR]
+#
+# Needs the riscv32i-unknown-none-elf target. Paths are relative to clients/cli.
+set -euo pipefail
+
+cd "$(dirname "$0")/.."
+
+TARGET=riscv32i-unknown-none-elf
+# Keep in step with the registry in src/programs.rs
+GUESTS=(fib_input keccak_input)
+OUT_DIR="${1:-assets}"
+TARGET_DIR="$PWD/target/guests"
+CARGO_HOME="${CARGO_HOME:-$HOME/.cargo}"
+
+sha256() {
+    if command -v sha256sum >/dev/null; then
+        sha256sum "$1" | cut -d' ' -f1
+    else
+        shasum -a 256 "$1" | cut -d' ' -f1
+    fi
+}
+
+# The examples' cargo config selects the target and its linker script. Paths into the
+# cargo home are remapped, so the ELFs do not depend on where the sources were fetched.
+# Flags meant for the CLI's own build must not leak into the guests'.
+(
+    cd examples
+    env -u CARGO_ENCODED_RUSTFLAGS -u RUSTFLAGS -u RUSTC_WRAPPER -u RUSTC_WORKSPACE_WRAPPER \
+        cargo build --release --bins --target-dir "$TARGET_DIR" \
+        --config "target.$TARGET.rustflags=[\"--remap-path-prefix=$CARGO_HOME=/cargo\"]"
+)
+
+# Where the examples' nexus-rt comes from, as locked in Cargo.lock
+NEXUS_RT=$(awk '
+    /^\[\[package\]\]/ { in_package = 0 }
+    $0 == "name = \"nexus-rt\"" { in_package = 1 }
+    in_package && /^source = / { gsub(/"/, "", $3); print $3; exit }
+' Cargo.lock)
+if [ -z "$NEXUS_RT" ]; then
+    echo "nexus-rt is not in Cargo.lock" >&2
+    exit 1
+fi
+
+mkdir -p "$OUT_DIR"
+PROGRAMS=""
+for guest in "${GUESTS[@]}"; do
+    cp "$TARGET_DIR/$TARGET/release/$guest" "$OUT_DIR/$guest"
+    source="examples/src/bin/$guest.rs"
+    [ -n "$PROGRAMS" ] && PROGRAMS+=$',\n'
+    PROGRAMS+=$(printf '    {\n      "name": "%s",\n      "sha256": "%s",\n      "source": "%s",\n      "source_sha256": "%s"\n    }' \
+        "$guest" "$(sha256 "$OUT_DIR/$guest")" "$source" "$(sha256 "$source")")
+done
+
+# The toolchain as rust-toolchain.toml selects it for the examples
+cat > "$OUT_DIR/manifest.json" <<EOF
+{
+  "cargo": "$(cd examples && cargo --version)",
+  "nexus_rt": "$NEXUS_RT",
+  "programs": [
+$PROGRAMS
+  ],
+  "rustc": "$(cd examples && rustc --version)",
+  "target": "$TARGET"
+}
+EOF
+echo "Bundled ${#GUESTS[@]} guest program(s) in $OUT_DIR/"
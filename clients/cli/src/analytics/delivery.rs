@@ -0,0 +1,353 @@
+//! Background delivery of analytics events to Google Analytics.
+//!
+//! A single worker task owns the HTTP client for the lifetime of the process:
+//! - Events are queued on a bounded channel and never block the prover
+//! - Queued events are grouped into Measurement Protocol batches
+//! - Batches that cannot be delivered are spilled to a spool file on disk
+//! - Delivery is retried with exponential backoff, and flushed on shutdown
+
+use reqwest::header::ACCEPT;
+use serde_json::{json, Value};
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+use tokio::sync::{mpsc, oneshot};
+use tokio::time::Instant;
+
+/// Number of events buffered in the channel before new ones are dropped
+const QUEUE_CAPACITY: usize = 256;
+/// The Measurement Protocol accepts at most 25 events per request
+const MAX_BATCH_EVENTS: usize = 25;
+/// Events kept in the spool file while offline; the oldest are discarded beyond this
+const MAX_SPOOLED_EVENTS: usize = 10_000;
+/// How often queued events are sent when the batch is not yet full
+const FLUSH_INTERVAL: Duration = Duration::from_secs(30);
+const INITIAL_BACKOFF: Duration = Duration::from_secs(30);
+const MAX_BACKOFF: Duration = Duration::from_secs(30 * 60);
+
+enum Message {
+    Event(Value),
+    Flush(oneshot::Sender<()>),
+}
+
+/// Handle to the background delivery worker
+pub struct Delivery {
+    tx: mpsc::Sender<Message>,
+    dropped: AtomicU64,
+    spool_path: PathBuf,
+}
+
+impl Delivery {
+    /// Spawn the worker, which sends with `client`. Must be called from within a tokio runtime.
+    pub fn spawn(client: reqwest::Client, url: String, spool_path: PathBuf) -> Self {
+        let (tx, rx) = mpsc::channel(QUEUE_CAPACITY);
+        let worker = Worker {
+            client,
+            url,
+            spool_path: spool_path.clone(),
+            pending: Vec::new(),
+            backoff: INITIAL_BACKOFF,
+            offline: false,
+        };
+        tokio::spawn(worker.run(rx));
+
+        Self {
+            tx,
+            dropped: AtomicU64::new(0),
+            spool_path,
+        }
+    }
+
+    pub fn spool_path(&self) -> &PathBuf {
+        &self.spool_path
+    }
+
+    /// Queue a Measurement Protocol payload without waiting.
+    ///
+    /// If the queue is full the event is dropped rather than stalling the caller.
+    pub fn enqueue(&self, body: Value) {
+        if self.tx.try_send(Message::Event(body)).is_err() {
+            let dropped = self.dropped.fetch_add(1, Ordering::Relaxed) + 1;
+            if dropped == 1 || dropped % 100 == 0 {
+                eprintln!(
+                    "Analytics queue is full; {} event(s) dropped so far",
+                    dropped
+                );
+            }
+        }
+    }
+
+    /// Ask the worker to send everything it holds, ignoring any backoff.
+    ///
+    /// The returned receiver completes once the attempt is over; anything that
+    /// could not be sent has been written to the spool file by then. It fails if the
+    /// worker has gone away, taking the queued events with it.
+    pub fn flush(&self) -> oneshot::Receiver<()> {
+        let (done_tx, done_rx) = oneshot::channel();
+        // Wait for room rather than dropping the flush behind a full queue
+        let tx = self.tx.clone();
+        tokio::spawn(async move {
+            let _ = tx.send(Message::Flush(done_tx)).await;
+        });
+        done_rx
+    }
+}
+
+struct Worker {
+    client: reqwest::Client,
+    url: String,
+    spool_path: PathBuf,
+    /// Payloads received since the last delivery attempt
+    pending: Vec<Value>,
+    backoff: Duration,
+    /// Whether the last delivery attempt failed
+    offline: bool,
+}
+
+impl Worker {
+    async fn run(mut self, mut rx: mpsc::Receiver<Message>) {
+        // Pick up anything left over from a previous run
+        let mut next_attempt = Instant::now();
+
+        loop {
+            tokio::select! {
+                message = rx.recv() => match message {
+                    Some(Message::Event(body)) => {
+                        self.pending.push(body);
+                        if self.pending.len() >= MAX_BATCH_EVENTS {
+                            if self.offline {
+                                // Keep memory bounded while the network is down
+                                let pending = std::mem::take(&mut self.pending);
+                                self.spill(&pending);
+                            } else {
+                                next_attempt = self.deliver().await;
+                            }
+                        }
+                    }
+                    Some(Message::Flush(done)) => {
+                        next_attempt = self.deliver().await;
+                        let _ = done.send(());
+                    }
+                    None => {
+                        self.deliver().await;
+                        return;
+                    }
+                },
+                _ = tokio::time::sleep_until(next_attempt) => {
+                    next_attempt = self.deliver().await;
+                }
+            }
+        }
+    }
+
+    /// Try to send the spooled and pending events, returning when to try next.
+    ///
+    /// The spool file is only rewritten once the attempt is over, so spooled events
+    /// survive a crash or a failed request.
+    async fn deliver(&mut self) -> Instant {
+        let mut payloads = self.read_spool();
+        payloads.append(&mut self.pending);
+
+        let batches = batches(&payloads);
+        let mut sent = 0;
+        for batch in &batches {
+            if let Err(e) = self.post(batch).await {
+                eprintln!(
+                    "Analytics delivery failed, keeping {} batch(es) for later: {}",
+                    batches.len() - sent,
+                    e
+                );
+                break;
+            }
+            sent += 1;
+        }
+
+        let remaining = &batches[sent..];
+        self.write_spool(remaining);
+        if remaining.is_empty() {
+            self.offline = false;
+            self.backoff = INITIAL_BACKOFF;
+            Instant::now() + FLUSH_INTERVAL
+        } else {
+            self.offline = true;
+            let retry_in = self.backoff;
+            self.backoff = (self.backoff * 2).min(MAX_BACKOFF);
+            Instant::now() + retry_in
+        }
+    }
+
+    async fn post(&self, batch: &Value) -> Result<(), Box<dyn std::error::Error>> {
+        let response = self
+            .client
+            .post(&self.url)
+            .json(batch)
+            .header(ACCEPT, "application/json")
+            .timeout(Duration::from_secs(10))
+            .send()
+            .await?;
+
+        let status = response.status();
+        if status.is_server_error() {
+            return Err(format!("server responded with {}", status).into());
+        }
+        if !status.is_success() {
+            // Retrying a rejected payload will not help, so it is dropped here
+            let error_text = response.text().await.unwrap_or_default();
+            eprintln!(
+                "Analytics request rejected with status {}: {}",
+                status, error_text
+            );
+        }
+        Ok(())
+    }
+
+    fn read_spool(&self) -> Vec<Value> {
+        let Ok(content) = fs::read_to_string(&self.spool_path) else {
+            return Vec::new();
+        };
+        content
+            .lines()
+            .filter_map(|line| serde_json::from_str(line).ok())
+            .collect()
+    }
+
+    /// Append payloads to the spool file
+    fn spill(&self, payloads: &[Value]) {
+        let mut spooled = self.read_spool();
+        spooled.extend_from_slice(payloads);
+        self.write_spool(&spooled);
+    }
+
+    /// Replace the spool file with `payloads`, discarding the oldest events beyond the
+    /// cap, or remove it if there are none
+    fn write_spool(&self, payloads: &[Value]) {
+        if payloads.is_empty() {
+            let _ = fs::remove_file(&self.spool_path);
+            return;
+        }
+
+        let mut spooled = payloads.to_vec();
+        let mut events = event_count(&spooled);
+        while events > MAX_SPOOLED_EVENTS && !spooled.is_empty() {
+            events -= event_count(&spooled[..1]);
+            spooled.remove(0);
+        }
+
+        if let Some(dir) = self.spool_path.parent() {
+            let _ = fs::create_dir_all(dir);
+        }
+        let result = OpenOptions::new()
+            .create(true)
+            .write(true)
+            .truncate(true)
+            .open(&self.spool_path)
+            .and_then(|mut file| {
+                for payload in &spooled {
+                    writeln!(file, "{}", payload)?;
+                }
+                Ok(())
+            });
+
+        if let Err(e) = result {
+            eprintln!(
+                "Failed to spool analytics events to {}: {}",
+                self.spool_path.to_string_lossy(),
+                e
+            );
+        }
+    }
+}
+
+fn event_count(payloads: &[Value]) -> usize {
+    payloads
+        .iter()
+        .map(|payload| payload["events"].as_array().map_or(0, Vec::len))
+        .sum()
+}
+
+/// Group payloads into Measurement Protocol requests.
+///
+/// Consecutive events for the same client are merged, up to the per-request limit.
+fn batches(payloads: &[Value]) -> Vec<Value> {
+    let mut batches: Vec<Value> = Vec::new();
+
+    for payload in payloads {
+        let client_id = &payload["client_id"];
+        let Some(events) = payload["events"].as_array() else {
+            continue;
+        };
+
+        for event in events {
+            match batches.last_mut() {
+                Some(batch)
+                    if batch["client_id"] == *client_id
+                        && event_count(std::slice::from_ref(batch)) < MAX_BATCH_EVENTS =>
+                {
+                    if let Some(batch_events) = batch["events"].as_array_mut() {
+                        batch_events.push(event.clone());
+                    }
+                }
+                _ => batches.push(json!({
+                    "client_id": client_id,
+                    "events": [event],
+                })),
+            }
+        }
+    }
+
+    batches
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn payload(client_id: &str, n: usize) -> Value {
+        json!({
+            "client_id": client_id,
+            "events": [{ "name": "test", "params": { "n": n } }],
+        })
+    }
+
+    #[test]
+    fn test_batches_group_by_client_and_respect_limit() {
+        let mut payloads: Vec<Value> = (0..30).map(|n| payload("a", n)).collect();
+        payloads.push(payload("b", 30));
+
+        let batches = batches(&payloads);
+
+        assert_eq!(batches.len(), 3);
+        assert_eq!(event_count(&batches[..1]), MAX_BATCH_EVENTS);
+        assert_eq!(batches[1]["client_id"], "a");
+        assert_eq!(event_count(&batches[1..2]), 5);
+        assert_eq!(batches[2]["client_id"], "b");
+    }
+
+    #[tokio::test]
+    async fn test_undelivered_events_are_spooled_and_flushed() {
+        let spool_path =
+            std::env::temp_dir().join(format!("nexus-analytics-spool-{}", std::process::id()));
+        // Left over from a previous run
+        fs::write(&spool_path, format!("{}\n", payload("a", 0))).unwrap();
+
+        // Nothing listens on this port, so every delivery attempt fails
+        let delivery = Delivery::spawn(
+            reqwest::Client::new(),
+            "http://127.0.0.1:9/collect".to_string(),
+            spool_path,
+        );
+        delivery.enqueue(payload("a", 1));
+        delivery.enqueue(payload("a", 2));
+        delivery.flush().await.unwrap();
+
+        let spooled = fs::read_to_string(delivery.spool_path()).unwrap();
+        let spooled: Vec<Value> = spooled
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(event_count(&spooled), 3);
+        fs::remove_file(delivery.spool_path()).unwrap();
+    }
+}
@@ -0,0 +1,518 @@
+mod delivery;
+
+use crate::config::{analytics_api_key, analytics_id, Environment};
+use crate::network::{self, NetworkSettings};
+use crate::state;
+use chrono::Datelike;
+use chrono::Timelike;
+use delivery::Delivery;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use sha3::{Digest, Keccak256};
+use std::{
+    env,
+    fs::OpenOptions,
+    io::Write,
+    path::PathBuf,
+    sync::OnceLock,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+use tokio::sync::oneshot;
+
+/// Event properties that can place a node geographically.
+const LOCATION_FIELDS: &[&str] = &["timezone", "local_hour"];
+
+/// Where analytics events are delivered.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum SinkKind {
+    /// Post events to Google Analytics via the Measurement Protocol
+    #[default]
+    GoogleAnalytics,
+    /// Append events as JSON lines to a local file
+    File,
+    /// Discard every event
+    Null,
+}
+
+/// How identifying fields are treated before an event leaves the node.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum Redaction {
+    /// Send `node_id` and location fields as-is
+    #[default]
+    Keep,
+    /// Replace `node_id` with a one-way hash and drop location fields
+    Hash,
+    /// Drop `node_id` and location fields entirely
+    Drop,
+}
+
+/// Analytics preferences, read from the `analytics` section of the settings file.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AnalyticsSettings {
+    /// Whether any events are recorded at all.
+    pub enabled: bool,
+    /// Where events are delivered.
+    pub sink: SinkKind,
+    /// Output path for the `file` sink. Defaults to `~/.nexus/analytics.jsonl`.
+    pub file: Option<PathBuf>,
+    /// Treatment of `node_id` and location fields.
+    pub redaction: Redaction,
+    /// Google Analytics measurement ID, overriding the one compiled into the binary.
+    pub measurement_id: Option<String>,
+    /// Google Analytics API secret, overriding the one compiled into the binary.
+    pub api_secret: Option<String>,
+}
+
+impl Default for AnalyticsSettings {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            sink: SinkKind::default(),
+            file: None,
+            redaction: Redaction::default(),
+            measurement_id: None,
+            api_secret: None,
+        }
+    }
+}
+
+/// A destination for analytics events.
+pub trait Sink: Send + Sync {
+    /// Short description of where events go, shown by `doctor`.
+    fn describe(&self) -> String;
+
+    /// Deliver a single Measurement Protocol payload.
+    fn send(&self, event_name: &str, body: Value);
+
+    /// Start sending anything still buffered. The receiver completes when done.
+    fn flush(&self) -> Option<oneshot::Receiver<()>> {
+        None
+    }
+}
+
+/// Posts events to Google Analytics through the background delivery worker.
+pub struct GoogleAnalyticsSink {
+    measurement_id: String,
+    delivery: Delivery,
+}
+
+impl GoogleAnalyticsSink {
+    fn new(
+        measurement_id: String,
+        api_secret: &str,
+        network: &NetworkSettings,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let url = format!(
+            "https://www.google-analytics.com/mp/collect?measurement_id={}&api_secret={}",
+            measurement_id, api_secret
+        );
+        let client = network::client_builder(network)?.build()?;
+        Ok(Self {
+            measurement_id,
+            delivery: Delivery::spawn(client, url, spool_path()),
+        })
+    }
+}
+
+fn describe_google_analytics(measurement_id: &str, spool: &std::path::Path) -> String {
+    format!(
+        "google-analytics (measurement id {}, offline spool {})",
+        measurement_id,
+        spool.to_string_lossy()
+    )
+}
+
+impl Sink for GoogleAnalyticsSink {
+    fn describe(&self) -> String {
+        describe_google_analytics(&self.measurement_id, self.delivery.spool_path())
+    }
+
+    fn send(&self, _event_name: &str, body: Value) {
+        self.delivery.enqueue(body);
+    }
+
+    fn flush(&self) -> Option<oneshot::Receiver<()>> {
+        Some(self.delivery.flush())
+    }
+}
+
+/// Appends one JSON object per event to a local file.
+pub struct FileSink {
+    path: PathBuf,
+}
+
+impl Sink for FileSink {
+    fn describe(&self) -> String {
+        format!("file ({})", self.path.to_string_lossy())
+    }
+
+    fn send(&self, event_name: &str, body: Value) {
+        let result = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .and_then(|mut file| writeln!(file, "{}", body));
+
+        if let Err(e) = result {
+            eprintln!(
+                "Failed to write analytics event '{}' to {}: {}",
+                event_name,
+                self.path.to_string_lossy(),
+                e
+            );
+        }
+    }
+}
+
+/// Says where events would go, but never sends them or starts delivery; for `doctor`.
+pub struct DescribeOnlySink(String);
+
+impl Sink for DescribeOnlySink {
+    fn describe(&self) -> String {
+        self.0.clone()
+    }
+
+    fn send(&self, _event_name: &str, _body: Value) {}
+}
+
+/// Discards every event.
+pub struct NullSink;
+
+impl Sink for NullSink {
+    fn describe(&self) -> String {
+        "disabled".to_string()
+    }
+
+    fn send(&self, _event_name: &str, _body: Value) {}
+}
+
+struct Analytics {
+    sink: Box<dyn Sink>,
+    redaction: Redaction,
+}
+
+impl Analytics {
+    /// Without `deliver`, a Google Analytics sink is only described: nothing is sent and
+    /// the spool is left alone.
+    fn new(
+        settings: &AnalyticsSettings,
+        network: &NetworkSettings,
+        environment: &Environment,
+        deliver: bool,
+    ) -> Self {
+        let sink: Box<dyn Sink> = if !settings.enabled {
+            Box::new(NullSink)
+        } else {
+            match settings.sink {
+                SinkKind::GoogleAnalytics => {
+                    let measurement_id = settings
+                        .measurement_id
+                        .clone()
+                        .unwrap_or_else(|| analytics_id(environment));
+                    let api_secret = settings
+                        .api_secret
+                        .clone()
+                        .unwrap_or_else(|| analytics_api_key(environment));
+
+                    // Builds without credentials never send anything
+                    if measurement_id.is_empty() || api_secret.is_empty() {
+                        Box::new(NullSink)
+                    } else if !deliver {
+                        Box::new(DescribeOnlySink(describe_google_analytics(
+                            &measurement_id,
+                            &spool_path(),
+                        )))
+                    } else {
+                        match GoogleAnalyticsSink::new(measurement_id, &api_secret, network) {
+                            Ok(sink) => Box::new(sink),
+                            Err(e) => {
+                                eprintln!("Analytics disabled: {}", e);
+                                Box::new(NullSink)
+                            }
+                        }
+                    }
+                }
+                SinkKind::File => Box::new(FileSink {
+                    path: settings.file.clone().unwrap_or_else(default_file_path),
+                }),
+                SinkKind::Null => Box::new(NullSink),
+            }
+        };
+
+        Self {
+            sink,
+            redaction: settings.redaction,
+        }
+    }
+}
+
+static ANALYTICS: OnceLock<Analytics> = OnceLock::new();
+
+/// Configure analytics for the lifetime of the process.
+///
+/// Must be called before the first `track`; later calls are ignored.
+pub fn init(settings: &AnalyticsSettings, network: &NetworkSettings, environment: &Environment) {
+    let _ = ANALYTICS.set(Analytics::new(settings, network, environment, true));
+}
+
+fn analytics(environment: &Environment) -> &'static Analytics {
+    ANALYTICS.get_or_init(|| {
+        Analytics::new(
+            &AnalyticsSettings::default(),
+            &NetworkSettings::default(),
+            environment,
+            true,
+        )
+    })
+}
+
+/// Send any buffered events, waiting at most `timeout`. Call before the process exits.
+pub async fn shutdown(timeout: Duration) {
+    let Some(done) = ANALYTICS.get().and_then(|analytics| analytics.sink.flush()) else {
+        return;
+    };
+    match tokio::time::timeout(timeout, done).await {
+        Ok(Ok(())) => {}
+        Ok(Err(_)) => {
+            eprintln!("Analytics delivery stopped before flushing; queued events were lost")
+        }
+        Err(_) => {
+            eprintln!("Timed out flushing analytics events; they will be sent on the next run")
+        }
+    }
+}
+
+fn default_file_path() -> PathBuf {
+    home::home_dir()
+        .unwrap_or_default()
+        .join(".nexus")
+        .join("analytics.jsonl")
+}
+
+/// Where undelivered events are kept, in the state directory
+fn spool_path() -> PathBuf {
+    state::path(state::ANALYTICS_SPOOL_FILE)
+}
+
+pub fn track(
+    event_name: String,
+    description: String,
+    event_properties: Value,
+    print_description: bool,
+    environment: &Environment,
+    client_id: String,
+) {
+    if print_description {
+        println!("{}", description);
+    }
+
+    let analytics = analytics(environment);
+    let body = build_payload(&event_name, &event_properties, &client_id);
+    let body = redact(body, analytics.redaction);
+    analytics.sink.send(&event_name, body);
+}
+
+/// Describe where events would go, along with a sample of what would be sent, for
+/// `doctor`. Nothing is sent.
+pub fn describe(
+    settings: &AnalyticsSettings,
+    network: &NetworkSettings,
+    environment: &Environment,
+) -> (String, Value) {
+    let analytics = Analytics::new(settings, network, environment, false);
+    let summary = format!(
+        "{}, redaction: {:?}",
+        analytics.sink.describe(),
+        analytics.redaction
+    )
+    .to_lowercase();
+
+    let sample = build_payload(
+        "cli_proof_node_v2",
+        &json!({
+            "node_id": "<node id>",
+            "proof_count": 1,
+        }),
+        &format!("{:x}", md5::compute(b"<node id>")),
+    );
+
+    (summary, redact(sample, analytics.redaction))
+}
+
+fn build_payload(event_name: &str, event_properties: &Value, client_id: &str) -> Value {
+    let local_now = chrono::offset::Local::now();
+
+    // For tracking events, we use the Firebase Measurement Protocol
+    // Firebase is mostly designed for mobile and web apps, but for our use case of a CLI,
+    // we can use the Measurement Protocol to track events by POST to a URL.
+    // The only thing that may be unexpected is that the URL we use includes a firebase key
+
+    // Firebase format for properties for Measurement protocol:
+    // https://developers.google.com/analytics/devguides/collection/protocol/ga4/reference?client_type=firebase#payload
+    // https://developers.google.com/analytics/devguides/collection/protocol/ga4/reference?client_type=firebase#payload_query_parameters
+
+    let system_time = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_else(|e| {
+            eprintln!("Error calculating system time: {}", e);
+            std::time::Duration::from_secs(0) // fallback to epoch start
+        })
+        .as_millis();
+
+    let timezone = iana_time_zone::get_timezone().ok().map_or_else(
+        || String::from("UTC"), // fallback to UTC
+        |tz| tz,
+    );
+
+    let mut properties = json!({
+        "time": system_time,
+        "platform": "CLI",
+        "os": env::consts::OS,
+        "os_version": env::consts::OS,  // We could get more specific version if needed
+        "app_version": env!("CARGO_PKG_VERSION"),
+        "node_id": event_properties["node_id"],
+        "timezone": timezone,
+        "local_hour": local_now.hour(),
+        "day_of_week": local_now.weekday().number_from_monday(),
+        "event_id": system_time,
+    });
+
+    // Add event properties to the properties JSON
+    // This is done by iterating over the key-value pairs in the event_properties JSON object
+    // but checking that it is a valid JSON object first
+    match event_properties.as_object() {
+        Some(obj) => {
+            for (k, v) in obj {
+                properties[k] = v.clone();
+            }
+        }
+        None => eprintln!("Warning: event_properties is not a valid JSON object"),
+    }
+
+    // Format for events
+    json!({
+        "client_id": client_id,
+        "events": [{
+            "name": event_name,
+            "params": properties
+        }],
+    })
+}
+
+/// Apply the redaction policy to a Measurement Protocol payload.
+fn redact(mut body: Value, redaction: Redaction) -> Value {
+    if redaction == Redaction::Keep {
+        return body;
+    }
+
+    // The client ID is derived from the node ID, so it is hashed or replaced along with it
+    body["client_id"] = match redaction {
+        Redaction::Hash => json!(hash_identifier(
+            body["client_id"].as_str().unwrap_or_default()
+        )),
+        _ => json!(format!("{:x}", md5::compute(b"anonymous"))),
+    };
+
+    if let Some(events) = body["events"].as_array_mut() {
+        for event in events {
+            let Some(params) = event["params"].as_object_mut() else {
+                continue;
+            };
+            for field in LOCATION_FIELDS {
+                params.remove(*field);
+            }
+            match redaction {
+                Redaction::Hash => {
+                    if let Some(node_id) = params.get("node_id").and_then(Value::as_str) {
+                        let hashed = hash_identifier(node_id);
+                        params.insert("node_id".to_string(), json!(hashed));
+                    }
+                }
+                _ => {
+                    params.remove("node_id");
+                }
+            }
+        }
+    }
+
+    body
+}
+
+/// One-way hash for identifiers, stable across runs so events can still be grouped.
+fn hash_identifier(value: &str) -> String {
+    let digest = Keccak256::digest(format!("nexus-analytics|{}", value));
+    format!("{:x}", digest)[..16].to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample() -> Value {
+        build_payload(
+            "test_event",
+            &json!({ "node_id": "1234", "proof_count": 2 }),
+            "client",
+        )
+    }
+
+    #[test]
+    fn test_hash_replaces_node_id_and_drops_location() {
+        let body = redact(sample(), Redaction::Hash);
+        let params = &body["events"][0]["params"];
+        assert_eq!(params["node_id"], hash_identifier("1234"));
+        assert_ne!(body["client_id"], "client");
+        for field in LOCATION_FIELDS {
+            assert!(params.get(*field).is_none());
+        }
+        assert_eq!(params["proof_count"], 2);
+    }
+
+    #[test]
+    fn test_drop_removes_node_id_and_location() {
+        let body = redact(sample(), Redaction::Drop);
+        let params = &body["events"][0]["params"];
+        assert!(params.get("node_id").is_none());
+        for field in LOCATION_FIELDS {
+            assert!(params.get(*field).is_none());
+        }
+    }
+
+    #[test]
+    fn test_file_sink_appends_json_lines() {
+        let path =
+            std::env::temp_dir().join(format!("nexus-analytics-{}.jsonl", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let sink = FileSink { path: path.clone() };
+
+        sink.send("a", json!({ "n": 1 }));
+        sink.send("b", json!({ "n": 2 }));
+
+        let contents = std::fs::read_to_string(&path).unwrap();
+        let lines: Vec<Value> = contents
+            .lines()
+            .map(|l| serde_json::from_str(l).unwrap())
+            .collect();
+        assert_eq!(lines, vec![json!({ "n": 1 }), json!({ "n": 2 })]);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_describing_google_analytics_does_not_start_delivery() {
+        let settings = AnalyticsSettings {
+            measurement_id: Some("G-TEST".to_string()),
+            api_secret: Some("secret".to_string()),
+            ..AnalyticsSettings::default()
+        };
+
+        // Outside a runtime, starting the delivery worker would panic
+        let (summary, sample) =
+            describe(&settings, &NetworkSettings::default(), &Environment::Local);
+
+        assert!(summary.starts_with("google-analytics (measurement id g-test"));
+        assert_eq!(sample["events"][0]["name"], "cli_proof_node_v2");
+    }
+}
@@ -0,0 +1,114 @@
+//! Local copies of submitted proofs.
+//!
+//! When an archive directory is configured, every proof the node submits is also kept
+//! there, next to what the guest program printed:
+//! - `<task>.proof`: the proof exactly as uploaded
+//! - `<task>.json`: its hash and encoding, and how the guest ended
+//! - `<task>.log`: the guest's output
+//!
+//! `<task>` is the task ID. Characters that are not safe in a file name are replaced,
+//! and a short hash of the original ID is appended so that IDs like `a/b` and `a_b`
+//! do not share files.
+
+use crate::guest::GuestRun;
+use crate::proof_encoding::{self, EncodedProof};
+use serde_json::json;
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Save a submitted proof and its guest output under `dir`, creating it if needed
+pub fn save(
+    dir: &Path,
+    task_id: &str,
+    proof: &EncodedProof,
+    run: &GuestRun,
+) -> std::io::Result<PathBuf> {
+    fs::create_dir_all(dir)?;
+    let base = dir.join(file_stem(task_id));
+
+    fs::write(base.with_extension("proof"), &proof.bytes)?;
+    fs::write(base.with_extension("log"), &run.output)?;
+    let metadata = json!({
+        "task_id": task_id,
+        "proof_hash": proof.hash,
+        "proof_size": proof.bytes.len(),
+        "encoding": proof_encoding::describe(&proof.encoding),
+        "outcome": run.outcome.to_string(),
+        "public_output": run.public_output,
+        "cycles": run.cycles,
+    });
+    fs::write(
+        base.with_extension("json"),
+        serde_json::to_vec_pretty(&metadata)?,
+    )?;
+
+    Ok(base)
+}
+
+/// A file name for a task, keeping IDs from the orchestrator from escaping the directory
+fn file_stem(task_id: &str) -> String {
+    let stem: String = task_id
+        .chars()
+        .map(|c| match c {
+            'a'..='z' | 'A'..='Z' | '0'..='9' | '-' | '_' => c,
+            _ => '_',
+        })
+        .collect();
+    if stem == task_id {
+        return stem;
+    }
+    let hash = Sha256::digest(task_id.as_bytes());
+    format!("{}-{}", stem, hex::encode(&hash[..4]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::guest::Outcome;
+    use crate::nexus_orchestrator::ProofEncoding;
+
+    #[test]
+    fn test_proof_is_archived_with_guest_output() {
+        let dir = std::env::temp_dir().join(format!("nexus-archive-{}", std::process::id()));
+        let proof = EncodedProof {
+            bytes: b"proof".to_vec(),
+            hash: "abc".to_string(),
+            serialized_len: 5,
+            encoding: ProofEncoding::default(),
+        };
+        let run = GuestRun {
+            outcome: Outcome::Success,
+            proof: None,
+            output: "[55, 0, 0, 0, 0, 0]\n".to_string(),
+            public_output: None,
+            cycles: None,
+        };
+
+        let base = save(&dir, "../task/1", &proof, &run).unwrap();
+
+        assert!(base
+            .file_name()
+            .unwrap()
+            .to_str()
+            .unwrap()
+            .starts_with("___task_1-"));
+        assert_eq!(fs::read(base.with_extension("proof")).unwrap(), b"proof");
+        assert_eq!(
+            fs::read_to_string(base.with_extension("log")).unwrap(),
+            run.output
+        );
+        let metadata: serde_json::Value =
+            serde_json::from_slice(&fs::read(base.with_extension("json")).unwrap()).unwrap();
+        assert_eq!(metadata["outcome"], "succeeded");
+        assert_eq!(metadata["encoding"], "json");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_task_ids_that_differ_get_different_files() {
+        assert_eq!(file_stem("task-1_a"), "task-1_a");
+        assert_ne!(file_stem("a/b"), file_stem("a_b"));
+        assert_ne!(file_stem("a/b"), file_stem("a.b"));
+    }
+}
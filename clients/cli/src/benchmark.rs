@@ -0,0 +1,263 @@
+//! Cycle-count and proving-time regression tracking for the example guest programs.
+//!
+//! The benchmark proves each example at a few input sizes, and counts the cycles of
+//! each run from its execution trace. Results are compared with `benches/baseline.json`,
+//! which also records the nexus-sdk revision and guest sources it was measured with,
+//! and the benchmark fails if an example got slower by more than the thresholds below,
+//! or if the baseline has no measurement for it.
+//!
+//! It needs the `riscv32i-unknown-none-elf` target and takes a while, so it is ignored by
+//! default:
+//!
+//! ```sh
+//! cargo test --release benchmark -- --ignored
+//! # After an intended change, record a new baseline
+//! NEXUS_UPDATE_BASELINE=1 cargo test --release benchmark -- --ignored
+//! ```
+
+use crate::differential::{build_examples, crate_dir, GUEST_TARGET};
+use crate::guest;
+use crate::nexus_orchestrator::ProofEncoding;
+use crate::proof_encoding;
+use nexus_sdk::stwo::seq::Stwo;
+use nexus_sdk::{Local, Prover};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::time::Instant;
+
+/// Largest tolerated increase in cycles, in percent. Cycle counts are deterministic, so
+/// this only leaves room for deliberate small changes.
+const CYCLE_THRESHOLD_PERCENT: f64 = 5.0;
+
+/// Largest tolerated increase in proving time, in percent; timings are noisy
+const TIME_THRESHOLD_PERCENT: f64 = 25.0;
+
+/// Examples to measure, with the public inputs to measure them at: a number, or for
+/// `keccak_input` the length of the message to hash. Examples without inputs take none.
+const CASES: &[(&str, &[u32])] = &[
+    ("fib_input", &[9, 100, 1000]),
+    ("keccak_input", &[32, 1024, 32 * 1024]),
+    ("fact", &[]),
+    ("fib1000", &[]),
+    ("galeshapley", &[]),
+    ("keccak", &[]),
+    ("lambda_calculus", &[]),
+    ("palindromes", &[]),
+];
+
+/// One example, proven on one input
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Measurement {
+    example: String,
+    /// The public input, or the length of a `keccak_input` message
+    input: Option<u32>,
+    cycles: u64,
+    prove_ms: u64,
+    proof_size: usize,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct Baseline {
+    /// The nexus-sdk source locked in `Cargo.lock`
+    nexus_sdk: String,
+    /// SHA-256 of each example's source
+    guests: BTreeMap<String, String>,
+    measurements: Vec<Measurement>,
+}
+
+/// How much `current` grew over `baseline`, in percent
+fn growth(baseline: f64, current: f64) -> f64 {
+    if baseline == 0.0 {
+        return 0.0;
+    }
+    (current - baseline) / baseline * 100.0
+}
+
+/// The measurements of `current` that regressed from `baseline`, described with what
+/// changed since the baseline was recorded
+fn regressions(baseline: &Baseline, current: &Baseline) -> Vec<String> {
+    let mut context = Vec::new();
+    if baseline.nexus_sdk != current.nexus_sdk {
+        context.push(format!("nexus-sdk is now {}", current.nexus_sdk));
+    }
+
+    let mut regressions = Vec::new();
+    for measurement in &current.measurements {
+        let case = match measurement.input {
+            Some(input) => format!("{}({})", measurement.example, input),
+            None => measurement.example.clone(),
+        };
+        // A case nobody measured before would otherwise pass whatever it costs
+        let Some(before) = baseline.measurements.iter().find(|before| {
+            before.example == measurement.example && before.input == measurement.input
+        }) else {
+            regressions.push(format!(
+                "{}: not in the baseline; record it with NEXUS_UPDATE_BASELINE=1",
+                case
+            ));
+            continue;
+        };
+        let mut changed = context.clone();
+        if baseline.guests.get(&measurement.example) != current.guests.get(&measurement.example) {
+            changed.push("its source changed".to_string());
+        }
+        let since = if changed.is_empty() {
+            String::new()
+        } else {
+            format!(" ({})", changed.join(", "))
+        };
+
+        let cycle_growth = growth(before.cycles as f64, measurement.cycles as f64);
+        if cycle_growth > CYCLE_THRESHOLD_PERCENT {
+            regressions.push(format!(
+                "{}: {} cycles, up {:.1}% from {}{}",
+                case, measurement.cycles, cycle_growth, before.cycles, since
+            ));
+        }
+        let growth = growth(before.prove_ms as f64, measurement.prove_ms as f64);
+        if growth > TIME_THRESHOLD_PERCENT {
+            regressions.push(format!(
+                "{}: proved in {}ms, up {:.1}% from {}ms{}",
+                case, measurement.prove_ms, growth, before.prove_ms, since
+            ));
+        }
+    }
+    regressions
+}
+
+fn measure(elf: &Path, example: &str, input: Option<u32>) -> Measurement {
+    let elf = std::fs::read(elf).unwrap();
+    let prover = Stwo::<Local>::new_from_bytes(&elf).unwrap();
+    let started = Instant::now();
+    let run = match input {
+        Some(length) if example == "keccak_input" => {
+            guest::prove::<Vec<u8>, ()>(prover, Some(&elf), &vec![0xab; length as usize])
+        }
+        Some(input) => guest::prove::<u32, ()>(prover, Some(&elf), &input),
+        None => guest::prove::<(), ()>(prover, Some(&elf), &()),
+    }
+    .unwrap();
+    let prove_ms = started.elapsed().as_millis() as u64;
+    assert!(run.outcome.is_success(), "{} {}", example, run.outcome);
+    let proof = proof_encoding::encode(&run.proof.unwrap(), ProofEncoding::default()).unwrap();
+
+    Measurement {
+        example: example.to_string(),
+        input,
+        cycles: run
+            .cycles
+            .unwrap_or_else(|| panic!("{} could not be traced", example)),
+        prove_ms,
+        proof_size: proof.bytes.len(),
+    }
+}
+
+fn locked_nexus_sdk() -> String {
+    let lock = std::fs::read_to_string(crate_dir().join("Cargo.lock")).unwrap();
+    lock.split("[[package]]")
+        .find(|package| package.contains("name = \"nexus-sdk\""))
+        .and_then(|package| {
+            package
+                .lines()
+                .find_map(|line| line.strip_prefix("source = "))
+        })
+        .map(|source| source.trim_matches('"').to_string())
+        .unwrap_or_default()
+}
+
+#[test]
+#[ignore = "proves every example, which needs the riscv32i-unknown-none-elf target"]
+fn test_examples_have_not_regressed() {
+    let elfs = build_examples(GUEST_TARGET, &[]);
+    let mut current = Baseline {
+        nexus_sdk: locked_nexus_sdk(),
+        ..Baseline::default()
+    };
+    for &(example, inputs) in CASES {
+        let source = crate_dir()
+            .join("examples/src/bin")
+            .join(format!("{}.rs", example));
+        let source = std::fs::read(source).unwrap();
+        current
+            .guests
+            .insert(example.to_string(), hex::encode(Sha256::digest(source)));
+
+        let elf = elfs.join(example);
+        if inputs.is_empty() {
+            current.measurements.push(measure(&elf, example, None));
+        }
+        for &input in inputs {
+            current
+                .measurements
+                .push(measure(&elf, example, Some(input)));
+        }
+    }
+
+    let baseline_path = crate_dir().join("benches").join("baseline.json");
+    let current_json = serde_json::to_string_pretty(&current).unwrap() + "\n";
+    if std::env::var_os("NEXUS_UPDATE_BASELINE").is_some() {
+        std::fs::create_dir_all(baseline_path.parent().unwrap()).unwrap();
+        std::fs::write(&baseline_path, current_json).unwrap();
+        return;
+    }
+    std::fs::write(
+        crate_dir().join("target").join("benchmark.json"),
+        current_json,
+    )
+    .unwrap();
+
+    let baseline = std::fs::read(&baseline_path).unwrap_or_else(|e| {
+        panic!(
+            "Failed to read {}: {}; record it with NEXUS_UPDATE_BASELINE=1",
+            baseline_path.display(),
+            e
+        )
+    });
+    let baseline: Baseline = serde_json::from_slice(&baseline).unwrap();
+    let regressions = regressions(&baseline, &current);
+    assert!(regressions.is_empty(), "{}", regressions.join("\n"));
+}
+
+#[test]
+fn test_regressions_beyond_thresholds_are_flagged() {
+    let measurement = |example: &str, input, cycles, prove_ms| Measurement {
+        example: example.to_string(),
+        input,
+        cycles,
+        prove_ms,
+        proof_size: 1000,
+    };
+    let baseline = Baseline {
+        nexus_sdk: "neo#1".to_string(),
+        guests: BTreeMap::from([("fib_input".to_string(), "a".to_string())]),
+        measurements: vec![
+            measurement("fib_input", Some(9), 1000, 100),
+            measurement("fib_input", Some(100), 5000, 400),
+            measurement("fact", None, 300, 100),
+        ],
+    };
+    let current = Baseline {
+        nexus_sdk: "neo#2".to_string(),
+        guests: baseline.guests.clone(),
+        measurements: vec![
+            // Within the thresholds
+            measurement("fib_input", Some(9), 1040, 120),
+            // Too many cycles
+            measurement("fib_input", Some(100), 5500, 400),
+            // Too slow
+            measurement("fact", None, 300, 200),
+            // No baseline yet
+            measurement("keccak_input", Some(1), 100, 100),
+        ],
+    };
+
+    let regressions = regressions(&baseline, &current);
+
+    assert_eq!(regressions.len(), 3, "{:?}", regressions);
+    assert!(regressions[0].starts_with("fib_input(100): 5500 cycles, up 10.0% from 5000"));
+    assert!(regressions[0].ends_with("(nexus-sdk is now neo#2)"));
+    assert!(regressions[1].starts_with("fact: proved in 200ms"));
+    assert!(regressions[2].starts_with("keccak_input(1): not in the baseline"));
+}
@@ -1,3 +1,13 @@
+use crate::analytics::AnalyticsSettings;
+use crate::network::NetworkSettings;
+use crate::proving::ProvingSettings;
+use crate::telemetry::TracingSettings;
+use crate::tls::TlsSettings;
+use crate::utils::updater::UpdaterSettings;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::PathBuf;
+
 // The following enum is used to determine the environment from the web socket string
 #[derive(Debug, Clone)]
 pub enum Environment {
@@ -39,29 +49,81 @@ impl std::fmt::Display for Environment {
     }
 }
 
+/// User-editable settings, read from `~/.nexus/settings.json`.
+///
+/// Every section is optional. Command-line flags take precedence over the file.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Settings {
+    pub analytics: AnalyticsSettings,
+    pub tracing: TracingSettings,
+    pub network: NetworkSettings,
+    pub tls: TlsSettings,
+    pub proving: ProvingSettings,
+    pub updater: UpdaterSettings,
+}
+
+impl Settings {
+    pub fn path() -> Option<PathBuf> {
+        home::home_dir().map(|home| home.join(".nexus").join("settings.json"))
+    }
+
+    /// Load the settings file, falling back to defaults if it is missing or invalid.
+    pub fn load() -> Self {
+        let Some(path) = Self::path() else {
+            return Self::default();
+        };
+
+        match fs::read_to_string(&path) {
+            Ok(content) => serde_json::from_str(&content).unwrap_or_else(|e| {
+                eprintln!(
+                    "Ignoring invalid settings file {}: {}",
+                    path.to_string_lossy(),
+                    e
+                );
+                Self::default()
+            }),
+            Err(_) => Self::default(),
+        }
+    }
+}
+
+/// Google Analytics credentials are injected at build time and never checked in.
+/// A build without them has analytics disabled unless the settings file provides them.
 mod analytics {
-    pub const DEV_MEASUREMENT_ID: &str = "G-SWNG3LZDFR";
-    pub const STAGING_MEASUREMENT_ID: &str = "G-T0M0Q3V6WN";
-    pub const BETA_MEASUREMENT_ID: &str = "G-GLH0GMEEFH";
-    pub const DEV_API_SECRET: &str = "8ySxiKrtT8a76zClqqO8IQ";
-    pub const STAGING_API_SECRET: &str = "OI7H53soRMSDWfJf1ittHQ";
-    pub const BETA_API_SECRET: &str = "3wxu8FjVSPqOlxSsZEnBOw";
+    pub const DEV_MEASUREMENT_ID: Option<&str> = option_env!("NEXUS_ANALYTICS_DEV_MEASUREMENT_ID");
+    pub const STAGING_MEASUREMENT_ID: Option<&str> =
+        option_env!("NEXUS_ANALYTICS_STAGING_MEASUREMENT_ID");
+    pub const BETA_MEASUREMENT_ID: Option<&str> =
+        option_env!("NEXUS_ANALYTICS_BETA_MEASUREMENT_ID");
+    pub const DEV_API_SECRET: Option<&str> = option_env!("NEXUS_ANALYTICS_DEV_API_SECRET");
+    pub const STAGING_API_SECRET: Option<&str> = option_env!("NEXUS_ANALYTICS_STAGING_API_SECRET");
+    pub const BETA_API_SECRET: Option<&str> = option_env!("NEXUS_ANALYTICS_BETA_API_SECRET");
 }
 
 pub fn analytics_id(environment: &Environment) -> String {
     match environment {
-        Environment::Dev => analytics::DEV_MEASUREMENT_ID.to_string(),
-        Environment::Staging => analytics::STAGING_MEASUREMENT_ID.to_string(),
-        Environment::Beta => analytics::BETA_MEASUREMENT_ID.to_string(),
-        Environment::Local => String::new(),
+        Environment::Dev => analytics::DEV_MEASUREMENT_ID,
+        Environment::Staging => analytics::STAGING_MEASUREMENT_ID,
+        Environment::Beta => analytics::BETA_MEASUREMENT_ID,
+        Environment::Local => None,
     }
+    .unwrap_or_default()
+    .to_string()
 }
 
 pub fn analytics_api_key(environment: &Environment) -> String {
     match environment {
-        Environment::Dev => analytics::DEV_API_SECRET.to_string(),
-        Environment::Staging => analytics::STAGING_API_SECRET.to_string(),
-        Environment::Beta => analytics::BETA_API_SECRET.to_string(),
-        Environment::Local => String::new(),
+        Environment::Dev => analytics::DEV_API_SECRET,
+        Environment::Staging => analytics::STAGING_API_SECRET,
+        Environment::Beta => analytics::BETA_API_SECRET,
+        Environment::Local => None,
     }
+    .unwrap_or_default()
+    .to_string()
+}
+
+/// Whether this binary was built with analytics credentials for the given environment
+pub fn analytics_compiled_in(environment: &Environment) -> bool {
+    !analytics_id(environment).is_empty() && !analytics_api_key(environment).is_empty()
 }
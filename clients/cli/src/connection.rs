@@ -1,182 +1,393 @@
-use crate::analytics::track;
+//! Streaming session with the Nexus Orchestrator.
+//!
+//! Instead of polling for tasks, the node keeps one WebSocket open:
+//! - it registers once, right after connecting
+//! - the orchestrator pushes `ProverResponse`s, each carrying a `ProofRequest`
+//! - while proving, the node sends `Progress`; while idle, `Heartbeat`
+//! - each finished proof is sent back as a `Proof`, encoded as the request asked
+//!
+//! Every WebSocket frame holds one protobuf-encoded message. The node keeps reading the
+//! session while it proves, so pings are answered and a closed session stops the proof.
+
+use crate::config;
+use crate::lease::ProvingRate;
+use crate::network::{self, NetworkSettings};
+use crate::nexus_orchestrator::{
+    prover_request::Contents, Heartbeat, Network, Progress, Proof, ProofRequest, ProverRequest,
+    ProverRequestRegistration, ProverResponse, ProverType,
+};
+use crate::proof_encoding;
+use crate::proving::ProverPool;
 use colored::Colorize;
-use serde_json::json;
+use futures_util::stream::{SplitSink, SplitStream};
+use futures_util::{SinkExt, StreamExt};
+use log::warn;
+use prost::Message as _;
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 use tokio::net::TcpStream;
-use tokio_tungstenite::{MaybeTlsStream, WebSocketStream};
-use tokio_tungstenite::tungstenite::client::IntoClientRequest;
-use tokio_tungstenite::{connect_async_tls_with_config, Connector}; // 确保使用 Connector
-use native_tls::TlsConnector;
-
-pub async fn connect_to_orchestrator(
-    ws_addr: &str,
-) -> Result<WebSocketStream<MaybeTlsStream<TcpStream>>, Box<dyn std::error::Error + Send + Sync>> {
-    // Parse the WebSocket address
-    let request = ws_addr.into_client_request()?;
-
-    // Create a custom TLS connector that disables certificate validation
-    let tls_connector = TlsConnector::builder()
-        .danger_accept_invalid_certs(true) // 忽略无效证书
-        .danger_accept_invalid_hostnames(true) // 忽略主机名验证
-        .build()?;
-
-    // Use the Connector::NativeTls variant
-    let connector = Connector::NativeTls(tls_connector);
-
-    // Call connect_async_tls_with_config with the appropriate parameters
-    let (client, _) = connect_async_tls_with_config(request, None, true, Some(connector))
-        .await
-        .map_err(|e| {
-            let error_message = format!(
-                "Failed to connect to orchestrator at {}: {}",
-                ws_addr,
-                e
-            )
-            .red();
-            eprintln!("{}", error_message);
-            // Log failure to analytics
-            track(
-                "orchestrator_connection_failed".to_string(),
-                error_message.to_string(), // Convert ColoredString to String
-                ws_addr,                   // WebSocket address
-                json!({ "error": e.to_string() }), // Event properties
-                true                        // Print description
-            );
-            e
-        })?;
-
-    // Log success connection event
-    track(
-        "orchestrator_connection_success".to_string(),
-        "Successfully connected to orchestrator.".to_string(), // Description of the success
-        ws_addr, // WebSocket address
-        json!({}), // No additional properties
-        true       // Print description
-    );
-
-    Ok(client)
+use tokio_tungstenite::tungstenite::{self, Message};
+use tokio_tungstenite::{Connector, MaybeTlsStream, WebSocketStream};
+use tokio_util::sync::CancellationToken;
+
+/// How often an idle node tells the orchestrator it is still there
+const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(30);
+/// How often a proving node reports progress
+const PROGRESS_INTERVAL: Duration = Duration::from_secs(10);
+/// Longest wait between reconnection attempts
+const MAX_RECONNECT_DELAY: Duration = Duration::from_secs(64);
+
+type WsStream = WebSocketStream<MaybeTlsStream<TcpStream>>;
+type WsSink = SplitSink<WsStream, Message>;
+type WsSource = SplitStream<WsStream>;
+
+/// Proves one request, returning the proof to send back. Runs on the prover pool, and
+/// should give up once the token is cancelled.
+pub type ProveFn =
+    Arc<dyn Fn(ProofRequest, &CancellationToken) -> Result<Proof, String> + Send + Sync>;
+
+/// How a connected node gets its work from the orchestrator.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum Protocol {
+    /// Ask for a task over HTTP, prove it, submit it, repeat
+    #[default]
+    Polling,
+    /// Keep a WebSocket session open and have tasks pushed to the node
+    Streaming,
 }
 
-pub async fn connect_to_orchestrator_with_infinite_retry(
-    ws_addr: &str,
-    prover_id: &str,
-) -> WebSocketStream<MaybeTlsStream<TcpStream>> {
-    let mut attempt = 1;
+/// WebSocket URL of the streaming endpoint for an environment
+pub fn stream_url(environment: &config::Environment) -> String {
+    let base = environment.orchestrator_url();
+    let base = match base.split_once("://") {
+        Some(("https", rest)) => format!("wss://{}", rest),
+        Some((_, rest)) => format!("ws://{}", rest),
+        None => format!("ws://{}", base),
+    };
+    format!("{}/v3/prover", base)
+}
 
-    loop {
-        match connect_to_orchestrator(ws_addr).await {
-            Ok(client) => {
-                println!("\t✓ Connected to Nexus Network.");
-
-                track(
-                    "connected".into(),
-                    "Connected.".into(),
-                    ws_addr,
-                    json!({"prover_id": prover_id}),
-                    false,
-                );
-                return client;
-            }
-            Err(_e) => {
-                eprintln!(
-                    "Could not connect to orchestrator (attempt {}). Retrying in {} seconds...",
-                    attempt,
-                    2u64.pow(attempt.min(6)),
-                );
+/// Registration sent at the start of every session
+pub fn registration(node_id: &str, environment: &config::Environment) -> ProverRequestRegistration {
+    let network = match environment {
+        config::Environment::Local | config::Environment::Dev => Network::Devnet,
+        config::Environment::Staging | config::Environment::Beta => Network::Testnet,
+    };
+
+    ProverRequestRegistration {
+        prover_type: ProverType::Volunteer as i32,
+        prover_id: node_id.to_string(),
+        estimated_proof_cycles_hertz: None,
+        network: network as i32,
+        supported_serializations: proof_encoding::SUPPORTED_SERIALIZATIONS
+            .map(|serialization| serialization as i32)
+            .to_vec(),
+        supported_compressions: proof_encoding::SUPPORTED_COMPRESSIONS
+            .map(|compression| compression as i32)
+            .to_vec(),
+    }
+}
 
-                tokio::time::sleep(tokio::time::Duration::from_secs(2u64.pow(attempt.min(6))))
-                    .await;
+/// Keep a session open for as long as the node runs, reconnecting with backoff when it drops
+pub async fn run(
+    url: &str,
+    registration: ProverRequestRegistration,
+    prove: ProveFn,
+    pool: Arc<ProverPool>,
+    network: &NetworkSettings,
+    tls_config: Arc<rustls::ClientConfig>,
+) {
+    let mut attempt: u32 = 1;
 
-                attempt += 1;
+    loop {
+        match connect(url, network, &tls_config).await {
+            Ok(ws) => {
+                println!("{}", "✓ Connected to Nexus Network.".green());
+                attempt = 1;
+                match run_session(ws, registration.clone(), prove.clone(), &pool).await {
+                    Ok(()) => println!("Orchestrator closed the session."),
+                    Err(e) => eprintln!("Session with orchestrator failed: {}", e),
+                }
             }
+            Err(e) => eprintln!("Failed to connect to orchestrator at {}: {}", url, e),
         }
+
+        let delay = Duration::from_secs(2u64.pow(attempt.min(6))).min(MAX_RECONNECT_DELAY);
+        eprintln!(
+            "Reconnecting in {} seconds (attempt {})...",
+            delay.as_secs(),
+            attempt
+        );
+        tokio::time::sleep(delay).await;
+        attempt += 1;
     }
 }
 
-pub async fn connect_to_orchestrator_with_limited_retry(
-    ws_addr: &str,
-    prover_id: &str,
-) -> Result<WebSocketStream<MaybeTlsStream<TcpStream>>, Box<dyn std::error::Error + Send + Sync>> {
-    let max_attempts = 5;
-    let mut attempt = 1;
+/// Open the WebSocket, through the configured proxy if any
+async fn connect(
+    url: &str,
+    network: &NetworkSettings,
+    tls_config: &Arc<rustls::ClientConfig>,
+) -> Result<WsStream, Box<dyn std::error::Error>> {
+    let stream = network::connect(network, url).await?;
+    let connector = Connector::Rustls(tls_config.clone());
+    let (ws, _) =
+        tokio_tungstenite::client_async_tls_with_config(url, stream, None, Some(connector)).await?;
+    Ok(ws)
+}
+
+/// Register, then prove every request the orchestrator sends until it closes the session
+pub async fn run_session(
+    ws: WsStream,
+    registration: ProverRequestRegistration,
+    prove: ProveFn,
+    pool: &ProverPool,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let (mut sink, mut stream) = ws.split();
+    send(&mut sink, Contents::Registration(registration)).await?;
+
+    let mut heartbeat = tokio::time::interval(HEARTBEAT_INTERVAL);
+    heartbeat.tick().await; // The first tick completes immediately
 
     loop {
-        if attempt >= max_attempts {
-            return Err(format!("Failed to connect after {} attempts", max_attempts).into());
+        tokio::select! {
+            message = stream.next() => {
+                let Some(response) = receive(message)? else {
+                    return Ok(());
+                };
+                if let Some(request) = response.to_prove {
+                    if !prove_request(&mut sink, &mut stream, request, prove.clone(), pool).await? {
+                        return Ok(());
+                    }
+                    heartbeat.reset();
+                }
+            }
+            _ = heartbeat.tick() => send(&mut sink, Contents::Heartbeat(Heartbeat {})).await?,
         }
+    }
+}
 
-        match connect_to_orchestrator(ws_addr).await {
-            Ok(client) => {
-                track(
-                    "connected".into(),
-                    "Connected.".into(),
-                    ws_addr,
-                    json!({"prover_id": prover_id}),
-                    false,
-                );
-                println!("{}", "✓ Success! Connected to Nexus Network.\n".green());
-                return Ok(client);
-            }
-            Err(e) => {
-                if attempt >= max_attempts {
-                    return Err(format!(
-                        "Failed to connect after {} attempts: {}",
-                        max_attempts, e
-                    )
-                    .into());
-                }
+/// Decode a message from the orchestrator, or `None` once it closed the session.
+///
+/// Frames other than binary ones, like pings, carry no response. Pings are answered by
+/// the WebSocket layer as they are read.
+fn receive(
+    message: Option<Result<Message, tungstenite::Error>>,
+) -> Result<Option<ProverResponse>, Box<dyn std::error::Error>> {
+    match message {
+        None | Some(Ok(Message::Close(_))) => Ok(None),
+        Some(Ok(Message::Binary(bytes))) => Ok(Some(ProverResponse::decode(bytes.as_slice())?)),
+        Some(Ok(_)) => Ok(Some(ProverResponse::default())),
+        Some(Err(e)) => Err(e.into()),
+    }
+}
 
-                eprintln!(
-                    "Could not connect to orchestrator (attempt {}/{}). Retrying in {} seconds...",
-                    attempt,
-                    max_attempts,
-                    2u64.pow(attempt.min(6)),
-                );
+/// Prove one request on the prover pool, reporting progress until it finishes.
+///
+/// A failed proof is logged and not sent; the orchestrator reassigns it. Returns `false`
+/// if the orchestrator closed the session meanwhile, which abandons the proof.
+async fn prove_request(
+    sink: &mut WsSink,
+    stream: &mut WsSource,
+    request: ProofRequest,
+    prove: ProveFn,
+    pool: &ProverPool,
+) -> Result<bool, Box<dyn std::error::Error>> {
+    let steps_to_prove = request.steps_to_prove.unwrap_or_default();
+    let progress = |(completed_fraction, steps_proven): (f32, i32)| {
+        Contents::Progress(Progress {
+            completed_fraction,
+            steps_in_trace: 0,
+            steps_to_prove,
+            steps_proven,
+        })
+    };
 
-                tokio::time::sleep(tokio::time::Duration::from_secs(2u64.pow(attempt.min(6))))
-                    .await;
+    let rate = ProvingRate::load();
+    let started = Instant::now();
+    let task = pool.run(move |cancelled| prove(request, cancelled));
+    tokio::pin!(task);
+    let mut ticker = tokio::time::interval(PROGRESS_INTERVAL);
 
-                attempt += 1;
+    let result = loop {
+        tokio::select! {
+            result = &mut task => break result.unwrap_or_else(|stopped| Err(stopped.to_string())),
+            _ = ticker.tick() => {
+                let estimate = estimated_progress(rate, started.elapsed(), steps_to_prove);
+                send(sink, progress(estimate)).await?
             }
+            message = stream.next() => match receive(message)? {
+                // Dropping the task cancels the proof
+                None => return Ok(false),
+                Some(response) if response.to_prove.is_some() => {
+                    warn!("Ignoring a request the orchestrator sent while this node was proving")
+                }
+                Some(_) => {}
+            },
         }
+    };
+
+    match result {
+        Ok(proof) => {
+            send(sink, progress((1.0, steps_to_prove))).await?;
+            send(sink, Contents::Proof(proof)).await?;
+            println!("{}", "ZK proof successfully submitted".green());
+        }
+        Err(e) => eprintln!("Failed to prove request from orchestrator: {}", e),
     }
+    Ok(true)
+}
+
+/// The fraction and number of steps likely proven after `elapsed`, at this node's
+/// proving rate. The prover does not report its progress, so this is only an estimate,
+/// and it stops short of done until the proof is.
+fn estimated_progress(
+    rate: Option<ProvingRate>,
+    elapsed: Duration,
+    steps_to_prove: i32,
+) -> (f32, i32) {
+    let Some(rate) = rate.filter(|_| steps_to_prove > 0) else {
+        return (0.0, 0);
+    };
+    let steps = (rate.cycles_per_sec * elapsed.as_secs_f64()).min(steps_to_prove as f64 * 0.99);
+    ((steps / steps_to_prove as f64) as f32, steps as i32)
+}
+
+async fn send(sink: &mut WsSink, contents: Contents) -> Result<(), Box<dyn std::error::Error>> {
+    let request = ProverRequest {
+        contents: Some(contents),
+    };
+    sink.send(Message::Binary(request.encode_to_vec())).await?;
+    Ok(())
 }
 
 #[cfg(test)]
 mod tests {
-    use super::connect_to_orchestrator; // Just the function we're testing
-    use futures::{SinkExt, StreamExt};
+    use super::*;
+    use crate::nexus_orchestrator::{compiled_program, proof, CompiledProgram};
+    use crate::proving::ProvingSettings;
     use tokio::net::TcpListener;
-    use tokio_tungstenite::tungstenite::Message;
+
+    fn decode(message: Message) -> Contents {
+        ProverRequest::decode(message.into_data().as_slice())
+            .unwrap()
+            .contents
+            .unwrap()
+    }
 
     #[tokio::test]
-    async fn test_basic_connection() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
-        // Setup mock server
-        let listener = TcpListener::bind("127.0.0.1:0").await?;
-        let addr = listener.local_addr()?;
-        let ws_addr = format!("ws://{}/prove", addr);
+    async fn test_session_registers_proves_and_returns_proof() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("ws://{}/v3/prover", listener.local_addr().unwrap());
+
+        // Mock orchestrator: expect a registration, push one request, collect the reply
+        let server = tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let mut ws = tokio_tungstenite::accept_async(stream).await.unwrap();
 
-        // Spawn server that sends a test message
-        let server_handle = tokio::spawn(async move {
-            let (stream, _) = listener.accept().await?;
-            let mut ws_stream = tokio_tungstenite::accept_async(stream).await?;
+            let registration = decode(ws.next().await.unwrap().unwrap());
+            assert!(matches!(
+                registration,
+                Contents::Registration(r) if r.prover_id == "node-1"
+            ));
 
-            // SinkExt::send is available because of the import above
-            ws_stream.send(Message::Text("test".into())).await?;
+            let request = ProverResponse {
+                to_prove: Some(ProofRequest {
+                    program: Some(CompiledProgram {
+                        program: Some(compiled_program::Program::Rv32iElfBytes(b"elf".to_vec())),
+                    }),
+                    input: None,
+                    step_to_start: None,
+                    steps_to_prove: None,
+                    proof_encoding: None,
+                }),
+            };
+            ws.send(Message::Binary(request.encode_to_vec()))
+                .await
+                .unwrap();
+            // The node is busy proving, but must still answer
+            ws.send(Message::Ping(b"still there?".to_vec()))
+                .await
+                .unwrap();
 
-            Ok::<_, Box<dyn std::error::Error + Send + Sync>>(())
+            let mut progress = Vec::new();
+            let mut ponged = false;
+            loop {
+                match ws.next().await.unwrap().unwrap() {
+                    Message::Pong(_) => ponged = true,
+                    message => match decode(message) {
+                        Contents::Progress(p) => progress.push(p.completed_fraction),
+                        Contents::Proof(p) => {
+                            ws.close(None).await.unwrap();
+                            return (progress, ponged, p);
+                        }
+                        other => panic!("unexpected message: {:?}", other),
+                    },
+                }
+            }
         });
 
-        // Connect client
-        let mut client = connect_to_orchestrator(&ws_addr).await?;
+        let prove: ProveFn = Arc::new(|request: ProofRequest, _: &CancellationToken| match request
+            .program
+            .and_then(|p| p.program)
+        {
+            Some(compiled_program::Program::Rv32iElfBytes(elf)) if elf == b"elf" => {
+                std::thread::sleep(Duration::from_millis(200));
+                Ok(Proof {
+                    proof: Some(proof::Proof::StwoBytes(b"proof".to_vec())),
+                    ..Default::default()
+                })
+            }
+            _ => Err("unexpected program".to_string()),
+        });
+        let (ws, _) = tokio_tungstenite::connect_async(&url).await.unwrap();
+        run_session(
+            ws,
+            registration("node-1", &config::Environment::Local),
+            prove,
+            &ProverPool::new(&ProvingSettings::default()),
+        )
+        .await
+        .unwrap();
 
-        // StreamExt::next is available for receiving
-        if let Some(msg) = client.next().await {
-            assert_eq!(msg?.into_text()?, "test");
-        } else {
-            panic!("No message received");
-        }
+        let (progress, ponged, proof) = server.await.unwrap();
+        assert_eq!(progress.last(), Some(&1.0));
+        assert!(ponged);
+        assert_eq!(
+            proof.proof,
+            Some(proof::Proof::StwoBytes(b"proof".to_vec()))
+        );
+    }
+
+    #[test]
+    fn test_progress_is_estimated_from_the_proving_rate() {
+        let rate = Some(ProvingRate {
+            cycles_per_sec: 100.0,
+        });
+        let progress = |secs, steps| estimated_progress(rate, Duration::from_secs(secs), steps);
+
+        assert_eq!(progress(10, 4000), (0.25, 1000));
+        // Never reported as done before the proof is
+        assert_eq!(progress(100, 4000).1, 3960);
+        // Without a rate or a step count there is nothing to go by
+        assert_eq!(progress(10, 0), (0.0, 0));
+        assert_eq!(
+            estimated_progress(None, Duration::from_secs(10), 4000),
+            (0.0, 0)
+        );
+    }
 
-        server_handle.await??;
-        Ok(())
+    #[test]
+    fn test_stream_url_uses_websocket_scheme() {
+        assert_eq!(
+            stream_url(&config::Environment::Beta),
+            "wss://beta.orchestrator.nexus.xyz/v3/prover"
+        );
+        assert_eq!(
+            stream_url(&config::Environment::Local),
+            "ws://localhost:8080/v3/prover"
+        );
     }
 }
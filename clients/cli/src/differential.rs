@@ -0,0 +1,160 @@
+//! Differential tests of the example guest programs.
+//!
+//! Every example in `examples/src/bin` can be built natively as well as for the zkVM.
+//! These tests build both, run each example the two ways on the same input, and check
+//! that they print the same output and end the same way. The cycles each zkVM run took,
+//! counted from its execution trace, are written to `target/example-cycles.json`, for
+//! regression tracking.
+//!
+//! Building the zkVM side needs the `riscv32i-unknown-none-elf` target, so the tests are
+//! ignored by default:
+//!
+//! ```sh
+//! cargo test differential -- --ignored
+//! ```
+
+use crate::guest::{self, Outcome};
+use nexus_sdk::stwo::seq::Stwo;
+use nexus_sdk::{Local, Prover, Viewable};
+use serde::{de::DeserializeOwned, Serialize};
+use std::collections::BTreeMap;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+pub const GUEST_TARGET: &str = "riscv32i-unknown-none-elf";
+
+/// The public input an example reads
+#[derive(Debug, Clone, Copy)]
+enum ExampleInput {
+    None,
+    Number(u32),
+    /// Bytes, read natively as a line of text
+    Text(&'static str),
+}
+
+/// Every example, with its public input
+const EXAMPLES: &[(&str, ExampleInput)] = &[
+    ("example", ExampleInput::Number(10)),
+    ("fact", ExampleInput::None),
+    ("fail", ExampleInput::None),
+    ("fib", ExampleInput::None),
+    ("fib1000", ExampleInput::None),
+    ("fib_input", ExampleInput::Number(9)),
+    ("galeshapley", ExampleInput::None),
+    ("keccak", ExampleInput::None),
+    ("keccak_input", ExampleInput::Text("Hello, World!")),
+    ("lambda_calculus", ExampleInput::None),
+    ("palindromes", ExampleInput::None),
+];
+
+/// How one run of an example ended, and what it printed
+#[derive(Debug)]
+struct Run {
+    outcome: Outcome,
+    output: String,
+    /// Cycles the run took, for zkVM runs
+    cycles: Option<u64>,
+}
+
+pub fn crate_dir() -> &'static Path {
+    Path::new(env!("CARGO_MANIFEST_DIR"))
+}
+
+/// Build every example for `target`, returning the directory the binaries are in
+pub fn build_examples(target: &str, extra_args: &[&str]) -> PathBuf {
+    let status = Command::new(env!("CARGO"))
+        .current_dir(crate_dir().join("examples"))
+        .args(["build", "--release", "--bins", "--target", target])
+        .args(extra_args)
+        .status()
+        .unwrap();
+    assert!(
+        status.success(),
+        "failed to build the examples for {}",
+        target
+    );
+    crate_dir().join("target").join(target).join("release")
+}
+
+fn host_target() -> String {
+    let output = Command::new("rustc").arg("-vV").output().unwrap();
+    String::from_utf8(output.stdout)
+        .unwrap()
+        .lines()
+        .find_map(|line| line.strip_prefix("host: "))
+        .expect("rustc does not report its host")
+        .to_string()
+}
+
+/// Run a native example, handing it its input on stdin as the examples expect
+fn run_native(binary: &Path, input: ExampleInput) -> Run {
+    let mut child = Command::new(binary)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .unwrap();
+    let mut stdin = child.stdin.take().unwrap();
+    match input {
+        ExampleInput::None => {}
+        ExampleInput::Number(number) => writeln!(stdin, "{}", number).unwrap(),
+        ExampleInput::Text(text) => writeln!(stdin, "{}", text).unwrap(),
+    }
+    drop(stdin);
+    let output = child.wait_with_output().unwrap();
+
+    // A native panic exits with 101, where the zkVM uses its own panic code
+    let outcome = match output.status.code() {
+        Some(101) => Outcome::Panicked,
+        Some(code) => Outcome::from_exit_code(code as u32),
+        None => panic!("{} was killed", binary.display()),
+    };
+    Run {
+        outcome,
+        output: String::from_utf8(output.stdout).unwrap(),
+        cycles: None,
+    }
+}
+
+/// Execute an example in the zkVM, without proving it
+fn run_in_vm<T: Serialize + DeserializeOwned>(elf: &Path, input: &T) -> Run {
+    let elf = std::fs::read(elf).unwrap();
+    let view = Stwo::<Local>::new_from_bytes(&elf)
+        .unwrap()
+        .run_with_input::<(), T>(&(), input)
+        .unwrap();
+    Run {
+        outcome: Outcome::from_exit_code(view.exit_code().unwrap()),
+        output: view.logs().unwrap().concat(),
+        cycles: Some(guest::traced_cycles(&elf, &(), input).unwrap()),
+    }
+}
+
+#[test]
+#[ignore = "builds the examples, which needs the riscv32i-unknown-none-elf target"]
+fn test_examples_agree_natively_and_in_the_vm() {
+    let native_dir = build_examples(&host_target(), &[]);
+    let vm_dir = build_examples(GUEST_TARGET, &[]);
+
+    let mut cycles = BTreeMap::new();
+    let mut mismatches = Vec::new();
+    for &(name, input) in EXAMPLES {
+        let native = run_native(&native_dir.join(name), input);
+        let elf = vm_dir.join(name);
+        let vm = match input {
+            ExampleInput::None => run_in_vm(&elf, &()),
+            ExampleInput::Number(number) => run_in_vm(&elf, &number),
+            ExampleInput::Text(text) => run_in_vm(&elf, &text.as_bytes().to_vec()),
+        };
+
+        if native.outcome != vm.outcome || native.output != vm.output {
+            mismatches.push(format!("{}: native {:?}, zkVM {:?}", name, native, vm));
+        }
+        cycles.insert(name, vm.cycles);
+    }
+
+    let record = crate_dir().join("target").join("example-cycles.json");
+    std::fs::write(&record, serde_json::to_vec_pretty(&cycles).unwrap()).unwrap();
+    assert!(mismatches.is_empty(), "{}", mismatches.join("\n"));
+}
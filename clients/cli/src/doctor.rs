@@ -0,0 +1,114 @@
+use crate::analytics;
+use crate::config;
+use crate::state;
+use crate::telemetry;
+use crate::tls;
+use colored::Colorize;
+
+/// Print how this binary was built
+pub fn print_build_info() {
+    println!(
+        "{}: {}",
+        "Version".bold(),
+        env!("CARGO_PKG_VERSION").bright_cyan()
+    );
+    println!(
+        "{}: {}-{}",
+        "Platform".bold(),
+        std::env::consts::OS,
+        std::env::consts::ARCH
+    );
+    println!(
+        "{}: {}",
+        "Profile".bold(),
+        if cfg!(debug_assertions) {
+            "debug"
+        } else {
+            "release"
+        }
+    );
+
+    let environments = [
+        config::Environment::Dev,
+        config::Environment::Staging,
+        config::Environment::Beta,
+    ];
+    let analytics = environments
+        .iter()
+        .map(|environment| {
+            let state = if config::analytics_compiled_in(environment) {
+                "compiled in"
+            } else {
+                "not configured"
+            };
+            format!("{} {}", environment, state)
+        })
+        .collect::<Vec<_>>()
+        .join(", ");
+    println!("{}: {}", "Analytics".bold(), analytics);
+}
+
+/// Print the effective configuration of this node, one line per subsystem
+pub fn run(environment: &config::Environment, settings: &config::Settings) {
+    println!(
+        "{}: {}",
+        "Environment".bold(),
+        environment.to_string().bright_cyan()
+    );
+    println!(
+        "{}: {}",
+        "Orchestrator".bold(),
+        environment.orchestrator_url().bright_cyan()
+    );
+
+    let settings_path = config::Settings::path()
+        .map(|path| path.to_string_lossy().into_owned())
+        .unwrap_or_else(|| "(no home directory)".to_string());
+    println!(
+        "{}: {}",
+        "Settings file".bold(),
+        settings_path.bright_cyan()
+    );
+
+    println!(
+        "{}: {}",
+        "Network".bold(),
+        settings.network.describe().bright_cyan()
+    );
+    println!(
+        "{}: {}",
+        "TLS".bold(),
+        tls::describe(&settings.tls).bright_cyan()
+    );
+    println!(
+        "{}: {}",
+        "Proving".bold(),
+        settings.proving.describe().bright_cyan()
+    );
+
+    println!(
+        "{}: {}",
+        "State directory".bold(),
+        state::state_dir().to_string_lossy().bright_cyan()
+    );
+
+    println!(
+        "{}: {}",
+        "Tracing".bold(),
+        telemetry::describe(&settings.tracing).bright_cyan()
+    );
+    println!(
+        "{}: {}",
+        "Updates".bold(),
+        settings.updater.describe().bright_cyan()
+    );
+
+    let (summary, sample) =
+        analytics::describe(&settings.analytics, &settings.network, environment);
+    println!("{}: {}", "Analytics".bold(), summary.bright_cyan());
+    println!(
+        "{}:\n{}",
+        "Sample analytics event".bold(),
+        serde_json::to_string_pretty(&sample).unwrap_or_default()
+    );
+}
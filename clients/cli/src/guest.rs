@@ -0,0 +1,438 @@
+//! How a guest program's run ended.
+//!
+//! A guest that panics or exits with a non-zero code is still proven: the proof shows
+//! how it failed, and the node reports the outcome along with it instead of treating it
+//! as an error of its own. A guest that runs out of cycles never finishes, so there is
+//! no proof to send, only the outcome.
+//!
+//! Whatever the guest printed is collected from the zkVM's view and logged at debug
+//! level, under the `guest` target. Nodes that opt in also attach it to their
+//! submissions, so a task's requester can see the result.
+//!
+//! The public output a successful guest commits is decoded into the type its program
+//! declares (see [`crate::programs`]) and always sent with the proof, as JSON.
+//!
+//! The prover does not say how many cycles a run took. Where that is wanted, as when
+//! benchmarking, the guest is also traced in the zkVM's emulator, without proving, and
+//! the steps of the trace are counted. Proofs of tasks are not traced.
+
+use crate::nexus_orchestrator::{GuestOutcome, GuestOutcomeKind};
+use crate::orchestrator_client::TaskReport;
+use log::{debug, warn};
+use nexus_sdk::stwo::seq::{Error as ProverError, Proof, Stwo};
+use nexus_sdk::{KnownExitCodes, Local, Prover, Viewable};
+use nexus_vm::elf::ElfFile;
+use nexus_vm::error::VMError;
+use nexus_vm::trace::{k_trace, Trace};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+use std::str::FromStr;
+
+/// Most guest output attached to a submission; the rest is cut off
+const MAX_ATTACHED_OUTPUT: usize = 64 * 1024;
+
+/// How a guest program ended
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Outcome {
+    Success,
+    /// Exited with a non-zero code other than the panic code
+    Exited(u32),
+    Panicked,
+    /// Ran out of cycles, with the zkVM's description of the failure
+    CycleLimit(String),
+}
+
+impl Outcome {
+    pub fn from_exit_code(code: u32) -> Self {
+        match code {
+            0 => Outcome::Success,
+            code if code == KnownExitCodes::ExitPanic as u32 => Outcome::Panicked,
+            code => Outcome::Exited(code),
+        }
+    }
+
+    /// Recognise a prover error caused by the guest running out of cycles, which the
+    /// zkVM reports as an error rather than through the view
+    fn from_prover_error(error: &ProverError) -> Option<Self> {
+        match error {
+            ProverError::VMError(VMError::MaxCyclesExceeded) => {
+                Some(Outcome::CycleLimit(error.to_string()))
+            }
+            _ => None,
+        }
+    }
+
+    pub fn is_success(&self) -> bool {
+        *self == Outcome::Success
+    }
+
+    /// The outcome as reported to the orchestrator
+    pub fn to_proto(&self) -> GuestOutcome {
+        let (kind, exit_code, message) = match self {
+            Outcome::Success => (GuestOutcomeKind::Success, 0, String::new()),
+            Outcome::Exited(code) => (GuestOutcomeKind::NonZeroExit, *code, String::new()),
+            Outcome::Panicked => (
+                GuestOutcomeKind::Panic,
+                KnownExitCodes::ExitPanic as u32,
+                String::new(),
+            ),
+            Outcome::CycleLimit(message) => (GuestOutcomeKind::CycleLimit, 0, message.clone()),
+        };
+        GuestOutcome {
+            kind: kind as i32,
+            exit_code,
+            message,
+        }
+    }
+}
+
+impl std::fmt::Display for Outcome {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Outcome::Success => write!(f, "succeeded"),
+            Outcome::Exited(code) => write!(f, "exited with code {}", code),
+            Outcome::Panicked => write!(f, "panicked"),
+            Outcome::CycleLimit(message) => write!(f, "ran out of cycles ({})", message),
+        }
+    }
+}
+
+/// The result of proving a guest program
+#[derive(Serialize, Deserialize)]
+pub struct GuestRun {
+    pub outcome: Outcome,
+    /// `None` if the guest never finished
+    pub proof: Option<Proof>,
+    /// What the guest printed, empty if it never finished
+    pub output: String,
+    /// The public output of a successful run, if the program commits one
+    #[serde(with = "json_text")]
+    pub public_output: Option<serde_json::Value>,
+    /// Cycles the guest ran for in the zkVM, if its run could be traced
+    pub cycles: Option<u64>,
+}
+
+impl GuestRun {
+    /// What to tell the orchestrator about this run along with its proof
+    pub fn report(&self, attach_output: bool) -> TaskReport {
+        TaskReport {
+            guest_outcome: Some(self.outcome.to_proto()),
+            guest_output: attach_output.then(|| truncate(&self.output, MAX_ATTACHED_OUTPUT)),
+            public_output: self.public_output.as_ref().map(|output| output.to_string()),
+        }
+    }
+}
+
+/// A JSON value serialized as its text, for formats such as bincode that cannot read
+/// back an arbitrary value
+mod json_text {
+    use serde::de::Error;
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    pub fn serialize<S: Serializer>(
+        value: &Option<serde_json::Value>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        value
+            .as_ref()
+            .map(|value| value.to_string())
+            .serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Option<serde_json::Value>, D::Error> {
+        Option::<String>::deserialize(deserializer)?
+            .map(|text| serde_json::from_str(&text).map_err(D::Error::custom))
+            .transpose()
+    }
+}
+
+/// The longest prefix of `text` that fits in `max_len` bytes, without splitting a character
+fn truncate(text: &str, max_len: usize) -> String {
+    let mut end = text.len().min(max_len);
+    while !text.is_char_boundary(end) {
+        end -= 1;
+    }
+    text[..end].to_string()
+}
+
+/// Input to a guest program that is not registered, as given on the command line
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum Input {
+    #[default]
+    None,
+    /// Handed to the guest as a `Vec<u8>`
+    Bytes(Vec<u8>),
+    /// Handed to the guest as the Rust value it stands for: a number as an integer, a
+    /// string as a `String`, an array as a `Vec`. Use bytes for anything else.
+    Json(serde_json::Value),
+}
+
+impl Input {
+    /// The input as given on the command line, which [`Input::from_str`] reads back;
+    /// `None` if there is none
+    pub fn to_arg(&self) -> Option<String> {
+        match self {
+            Input::None => None,
+            Input::Bytes(bytes) => Some(format!("0x{}", hex::encode(bytes))),
+            Input::Json(value) => Some(value.to_string()),
+        }
+    }
+}
+
+impl FromStr for Input {
+    type Err = String;
+
+    /// Parse `0x<hex>` as bytes, `@<file>` as the contents of a file (JSON if it ends in
+    /// `.json`, bytes otherwise), and anything else as JSON
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        if let Some(hex) = input.strip_prefix("0x") {
+            return hex::decode(hex)
+                .map(Input::Bytes)
+                .map_err(|e| format!("Invalid hex input: {}", e));
+        }
+        if let Some(path) = input.strip_prefix('@') {
+            let path = Path::new(path);
+            let contents = std::fs::read(path)
+                .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+            if path
+                .extension()
+                .is_some_and(|extension| extension == "json")
+            {
+                return serde_json::from_slice(&contents)
+                    .map(Input::Json)
+                    .map_err(|e| format!("Invalid JSON in {}: {}", path.display(), e));
+            }
+            return Ok(Input::Bytes(contents));
+        }
+        serde_json::from_str(input)
+            .map(Input::Json)
+            .map_err(|e| format!("Input is neither 0x-prefixed hex, @file nor JSON: {}", e))
+    }
+}
+
+/// Prove a guest program, loaded into `prover`, that takes public input `T` and commits
+/// public output `U`, and find out how it ended. Given the guest's ELF as `traced`, the
+/// guest's cycles are also counted by tracing it.
+///
+/// Errors are failures of the prover itself, not of the guest.
+pub fn prove<T, U>(
+    prover: Stwo<Local>,
+    traced: Option<&[u8]>,
+    public_input: &T,
+) -> Result<GuestRun, String>
+where
+    T: Serialize + DeserializeOwned,
+    U: Serialize + DeserializeOwned,
+{
+    prove_with_private_input::<(), T, U>(prover, traced, &(), public_input)
+}
+
+/// [`prove`], for a guest program that also takes private input `S`
+pub fn prove_with_private_input<S, T, U>(
+    prover: Stwo<Local>,
+    traced: Option<&[u8]>,
+    private_input: &S,
+    public_input: &T,
+) -> Result<GuestRun, String>
+where
+    S: Serialize,
+    T: Serialize + DeserializeOwned,
+    U: Serialize + DeserializeOwned,
+{
+    match prover.prove_with_input::<S, T>(private_input, public_input) {
+        Ok((view, proof)) => {
+            let code = view
+                .exit_code()
+                .map_err(|e| format!("Failed to retrieve exit code: {:?}", e))?;
+            // The output is informational; a proof without it is still a proof
+            let output = view.logs().map(|logs| logs.concat()).unwrap_or_else(|e| {
+                warn!("Failed to retrieve guest output: {:?}", e);
+                String::new()
+            });
+            for line in output.lines() {
+                debug!(target: "guest", "{}", line);
+            }
+
+            let outcome = Outcome::from_exit_code(code);
+            // A failed guest may not have committed anything
+            let public_output = if outcome.is_success() {
+                decode_public_output::<U>(&view)
+            } else {
+                None
+            };
+
+            Ok(GuestRun {
+                outcome,
+                proof: Some(proof),
+                cycles: traced.and_then(|elf| {
+                    traced_cycles(elf, private_input, public_input)
+                        .map_err(|e| warn!("Failed to count the guest's cycles: {}", e))
+                        .ok()
+                }),
+                output,
+                public_output,
+            })
+        }
+        Err(e) => match Outcome::from_prover_error(&e) {
+            Some(outcome) => Ok(GuestRun {
+                outcome,
+                proof: None,
+                output: String::new(),
+                public_output: None,
+                cycles: None,
+            }),
+            None => Err(format!("Failed to run prover: {}", e)),
+        },
+    }
+}
+
+/// Cycles the zkVM runs the guest in `elf` for on these inputs, counted from its
+/// execution trace.
+///
+/// This runs the guest once more, without proving it, which takes little time next to
+/// a proof.
+pub fn traced_cycles<S: Serialize, T: Serialize>(
+    elf: &[u8],
+    private_input: &S,
+    public_input: &T,
+) -> Result<u64, String> {
+    let elf = ElfFile::from_bytes(elf).map_err(|e| format!("Invalid ELF: {:?}", e))?;
+    let (_, trace) = k_trace(
+        elf,
+        &[],
+        &encode_input(public_input)?,
+        &encode_input(private_input)?,
+        1,
+    )
+    .map_err(|e| format!("Failed to trace the guest: {:?}", e))?;
+    Ok(trace.get_num_steps() as u64)
+}
+
+/// An input as the SDK hands it to the guest: COBS-framed postcard, padded to a word
+fn encode_input<T: Serialize>(input: &T) -> Result<Vec<u8>, String> {
+    let invalid = |e: postcard::Error| format!("Failed to encode input: {}", e);
+    if postcard::to_stdvec(input).map_err(invalid)?.is_empty() {
+        return Ok(Vec::new());
+    }
+    let mut encoded = postcard::to_stdvec_cobs(input).map_err(invalid)?;
+    encoded.resize(encoded.len().next_multiple_of(4), 0);
+    Ok(encoded)
+}
+
+/// The public output as JSON; `None` if there is none, or it is not a `U`.
+///
+/// Like the guest's printed output, this accompanies the proof but is not needed for it.
+fn decode_public_output<U: Serialize + DeserializeOwned>(
+    view: &impl Viewable,
+) -> Option<serde_json::Value> {
+    let output = view
+        .public_output::<U>()
+        .map_err(|e| warn!("Failed to decode public output: {:?}", e))
+        .ok()?;
+    serde_json::to_value(output)
+        .map_err(|e| warn!("Failed to encode public output: {}", e))
+        .ok()
+        .filter(|output| !output.is_null())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::Path;
+
+    #[test]
+    fn test_outcomes_are_classified_and_reported() {
+        assert!(Outcome::from_exit_code(0).is_success());
+        assert_eq!(Outcome::from_exit_code(1), Outcome::Panicked);
+        assert_eq!(Outcome::from_exit_code(3), Outcome::Exited(3));
+        let exceeded = ProverError::VMError(VMError::MaxCyclesExceeded);
+        assert_eq!(
+            Outcome::from_prover_error(&exceeded),
+            Some(Outcome::CycleLimit(exceeded.to_string()))
+        );
+        assert_eq!(
+            Outcome::from_prover_error(&ProverError::VMError(VMError::VMOutOfInstructions)),
+            None
+        );
+
+        let reported = Outcome::Exited(3).to_proto();
+        assert_eq!(reported.kind(), GuestOutcomeKind::NonZeroExit);
+        assert_eq!(reported.exit_code, 3);
+    }
+
+    #[test]
+    fn test_attached_output_is_opt_in_and_bounded() {
+        let run = GuestRun {
+            outcome: Outcome::Success,
+            proof: None,
+            output: "é".repeat(MAX_ATTACHED_OUTPUT),
+            public_output: Some(serde_json::json!([55])),
+            cycles: None,
+        };
+        assert_eq!(run.report(false).guest_output, None);
+
+        let attached = run.report(true).guest_output.unwrap();
+        assert_eq!(attached.len(), MAX_ATTACHED_OUTPUT);
+        assert!(attached.chars().all(|c| c == 'é'));
+        // The public output is always reported
+        assert_eq!(run.report(false).public_output.as_deref(), Some("[55]"));
+    }
+
+    #[test]
+    fn test_inputs_are_parsed_as_hex_files_or_json() {
+        assert_eq!("".parse::<Input>().ok(), None);
+        assert_eq!("0x0a0b".parse(), Ok(Input::Bytes(vec![10, 11])));
+        assert_eq!("9".parse(), Ok(Input::Json(serde_json::json!(9))));
+        assert_eq!(
+            r#"["a", "b"]"#.parse(),
+            Ok(Input::Json(serde_json::json!(["a", "b"])))
+        );
+        assert!("@/no/such/file".parse::<Input>().is_err());
+
+        // As handed to a child prover
+        for input in [
+            Input::Bytes(vec![200]),
+            Input::Json(serde_json::json!([200])),
+        ] {
+            assert_eq!(input.to_arg().unwrap().parse(), Ok(input));
+        }
+        assert_eq!(Input::None.to_arg(), None);
+    }
+
+    #[test]
+    fn test_inputs_are_encoded_as_the_sdk_does() {
+        assert_eq!(encode_input(&()), Ok(vec![]));
+        // postcard's varint 9, COBS-framed and padded to four bytes
+        assert_eq!(encode_input(&9u32), Ok(vec![2, 9, 0, 0]));
+    }
+
+    #[test]
+    #[ignore = "builds the `fail` example, which needs the riscv32i-unknown-none-elf target"]
+    fn test_fail_example_is_reported_as_panic() {
+        let manifest_dir = Path::new(env!("CARGO_MANIFEST_DIR"));
+        // The examples' cargo config selects the RISC-V target
+        let status = std::process::Command::new(env!("CARGO"))
+            .args(["build", "--release", "--bin", "fail"])
+            .current_dir(manifest_dir.join("examples"))
+            .status()
+            .unwrap();
+        assert!(status.success());
+
+        let elf = manifest_dir
+            .join("target")
+            .join("riscv32i-unknown-none-elf")
+            .join("release")
+            .join("fail");
+        let elf = std::fs::read(elf).unwrap();
+        let prover = Stwo::<Local>::new_from_bytes(&elf).unwrap();
+        let run = prove::<(), ()>(prover, None, &()).unwrap();
+
+        assert_eq!(run.outcome, Outcome::Panicked);
+        assert!(run.proof.is_some());
+        assert_eq!(run.outcome.to_proto().kind(), GuestOutcomeKind::Panic);
+    }
+}
@@ -0,0 +1,239 @@
+//! Leases on tasks handed out by the orchestrator.
+//!
+//! A task may come with a lease: a deadline after which the orchestrator rejects its
+//! proof and gives the task to another node. The node:
+//! - skips tasks it estimates it cannot prove in time, releasing them right away
+//! - renews the lease while proving, before it runs out
+//! - stops waiting for the proof once the lease has expired, and never submits it
+//!
+//! Estimates come from this node's proving rate, the cycles per second it reached on
+//! earlier tasks. The rate is kept in the state directory across runs. A node without
+//! one measures it with a short calibration proof when it starts, so that it can skip
+//! tasks before it has proven any; if that fails, every task is accepted until one with
+//! an estimated cycle count has been proven.
+
+use crate::orchestrator_client::OrchestratorClient;
+use crate::programs;
+use crate::state;
+use serde::{Deserialize, Serialize};
+use std::future::Future;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tokio_util::sync::CancellationToken;
+
+/// Extra time allowed on top of the estimated proving time, for variance
+const SAFETY_FACTOR: f64 = 1.5;
+/// Time kept in reserve for serializing and uploading the proof
+const SUBMISSION_MARGIN: Duration = Duration::from_secs(30);
+/// Leases with less time left than this are not worth renewing
+const MIN_RENEWABLE: Duration = Duration::from_secs(5);
+/// Weight of the newest measurement in the proving rate
+const RATE_SMOOTHING: f64 = 0.3;
+/// Public input of the calibration proof, a short run of the default program
+const CALIBRATION_INPUT: &[u8] = &[12];
+
+/// This node's lease on one task
+#[derive(Debug, Clone)]
+pub struct Lease {
+    pub task_id: String,
+    /// When the lease ends; `None` if the task has no deadline
+    pub expires_at: Option<SystemTime>,
+}
+
+impl Lease {
+    pub fn new(task_id: &str, expires_at_ms: Option<u64>) -> Self {
+        Self {
+            task_id: task_id.to_string(),
+            expires_at: expires_at_ms.map(from_unix_ms),
+        }
+    }
+
+    /// Time left before the lease ends, or `None` if it never does
+    pub fn remaining(&self) -> Option<Duration> {
+        self.expires_at.map(|expires_at| {
+            expires_at
+                .duration_since(SystemTime::now())
+                .unwrap_or(Duration::ZERO)
+        })
+    }
+
+    pub fn is_expired(&self) -> bool {
+        self.remaining() == Some(Duration::ZERO)
+    }
+}
+
+fn from_unix_ms(ms: u64) -> SystemTime {
+    UNIX_EPOCH + Duration::from_millis(ms)
+}
+
+/// How fast this node proves, measured on completed tasks
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct ProvingRate {
+    pub cycles_per_sec: f64,
+}
+
+impl ProvingRate {
+    /// The rate saved by earlier runs, if any
+    pub fn load() -> Option<Self> {
+        let content = std::fs::read_to_string(state::path(state::BENCHMARK_FILE)).ok()?;
+        serde_json::from_str(&content)
+            .ok()
+            .filter(|rate: &Self| rate.cycles_per_sec.is_finite() && rate.cycles_per_sec > 0.0)
+    }
+
+    pub fn save(&self) {
+        let path = state::path(state::BENCHMARK_FILE);
+        let result = serde_json::to_string(self)
+            .map_err(std::io::Error::other)
+            .and_then(|content| std::fs::write(&path, content));
+        if let Err(e) = result {
+            eprintln!("Failed to save proving rate to {}: {}", path.display(), e);
+        }
+    }
+
+    /// Fold a completed task into the rate
+    pub fn record(rate: Option<Self>, cycles: u64, elapsed: Duration) -> Option<Self> {
+        let secs = elapsed.as_secs_f64();
+        if cycles == 0 || secs <= 0.0 {
+            return rate;
+        }
+        let measured = cycles as f64 / secs;
+        let cycles_per_sec = match rate {
+            Some(rate) => rate.cycles_per_sec * (1.0 - RATE_SMOOTHING) + measured * RATE_SMOOTHING,
+            None => measured,
+        };
+        Some(Self { cycles_per_sec })
+    }
+
+    /// Measure the rate with a short proof of the default program
+    pub fn calibrate(cancelled: &CancellationToken) -> Result<Self, String> {
+        let started = Instant::now();
+        let run = programs::PROGRAMS[0].prove_counting_cycles(CALIBRATION_INPUT, cancelled)?;
+        let cycles = run
+            .cycles
+            .ok_or("The calibration proof did not report its cycle count")?;
+        Self::record(None, cycles, started.elapsed())
+            .ok_or_else(|| "The calibration proof ran no cycles".to_string())
+    }
+
+    /// Expected proving time for a task, including the safety factor
+    pub fn estimate(&self, cycles: u64) -> Duration {
+        Duration::from_secs_f64(cycles as f64 / self.cycles_per_sec * SAFETY_FACTOR)
+    }
+}
+
+/// Why a task should be skipped, if this node cannot prove it before its lease ends
+pub fn reason_to_skip(
+    lease: &Lease,
+    estimated_cycles: Option<u64>,
+    rate: Option<ProvingRate>,
+) -> Option<String> {
+    let remaining = lease.remaining()?;
+    if remaining <= SUBMISSION_MARGIN {
+        return Some(format!("only {}s left on its lease", remaining.as_secs()));
+    }
+
+    let (Some(cycles), Some(rate)) = (estimated_cycles, rate) else {
+        return None;
+    };
+    let needed = rate.estimate(cycles) + SUBMISSION_MARGIN;
+    (needed > remaining).then(|| {
+        format!(
+            "{} cycles would take about {}s at {:.0} cycles/s, but its lease ends in {}s",
+            cycles,
+            needed.as_secs(),
+            rate.cycles_per_sec,
+            remaining.as_secs()
+        )
+    })
+}
+
+/// When to renew a lease with `remaining` time left: once two thirds of it have passed
+fn renewal_delay(remaining: Duration) -> Option<Duration> {
+    (remaining >= MIN_RENEWABLE).then(|| remaining * 2 / 3)
+}
+
+/// Wait for `work` while keeping the lease alive.
+///
+/// Returns `None` if the lease expired first; `work` is then dropped and its result
+/// must not be submitted.
+pub async fn hold_while<F: Future>(
+    work: F,
+    lease: &mut Lease,
+    client: &OrchestratorClient,
+    node_id: &str,
+) -> Option<F::Output> {
+    if lease.expires_at.is_none() {
+        return Some(work.await);
+    }
+    tokio::pin!(work);
+
+    loop {
+        let remaining = lease.remaining().unwrap_or_default();
+        if remaining.is_zero() {
+            return None;
+        }
+        let renewal = renewal_delay(remaining);
+
+        tokio::select! {
+            output = &mut work => return Some(output),
+            _ = tokio::time::sleep(remaining) => {}
+            _ = tokio::time::sleep(renewal.unwrap_or_default()), if renewal.is_some() => {
+                match client.renew_lease(node_id, &lease.task_id).await {
+                    Ok(expires_at_ms) => lease.expires_at = Some(from_unix_ms(expires_at_ms)),
+                    // Try again later; the lease itself is unchanged
+                    Err(e) => eprintln!("Failed to renew lease on task {}: {}", lease.task_id, e),
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lease_ending_in(secs: u64) -> Lease {
+        let expires_at = SystemTime::now() + Duration::from_secs(secs);
+        let ms = expires_at.duration_since(UNIX_EPOCH).unwrap().as_millis() as u64;
+        Lease::new("task-1", Some(ms))
+    }
+
+    #[test]
+    fn test_tasks_that_cannot_finish_in_time_are_skipped() {
+        let rate = Some(ProvingRate {
+            cycles_per_sec: 1000.0,
+        });
+
+        // 60k cycles take 90s with the safety factor, plus 30s to submit
+        assert!(reason_to_skip(&lease_ending_in(600), Some(60_000), rate).is_none());
+        assert!(reason_to_skip(&lease_ending_in(100), Some(60_000), rate).is_some());
+        // Nearly expired leases are skipped even without an estimate
+        assert!(reason_to_skip(&lease_ending_in(10), None, None).is_some());
+        // Without a deadline, or without a rate to judge by, tasks are accepted
+        assert!(reason_to_skip(&Lease::new("task-1", None), Some(u64::MAX), rate).is_none());
+        assert!(reason_to_skip(&lease_ending_in(100), Some(60_000), None).is_none());
+    }
+
+    #[test]
+    fn test_proving_rate_follows_measurements() {
+        let rate = ProvingRate::record(None, 10_000, Duration::from_secs(10));
+        assert_eq!(rate.unwrap().cycles_per_sec, 1000.0);
+
+        let rate = ProvingRate::record(rate, 20_000, Duration::from_secs(10));
+        assert!((rate.unwrap().cycles_per_sec - 1300.0).abs() < 1e-6);
+
+        // Measurements without cycles or time say nothing about the rate
+        assert_eq!(ProvingRate::record(rate, 0, Duration::from_secs(10)), rate);
+    }
+
+    #[test]
+    fn test_lease_is_renewed_before_it_runs_out() {
+        assert_eq!(
+            renewal_delay(Duration::from_secs(90)),
+            Some(Duration::from_secs(60))
+        );
+        assert_eq!(renewal_delay(Duration::from_secs(1)), None);
+        assert!(lease_ending_in(0).is_expired());
+        assert!(!lease_ending_in(60).is_expired());
+    }
+}
@@ -0,0 +1,330 @@
+//! Proving tasks from files instead of the orchestrator.
+//!
+//! For offline benchmarking and private workloads, `prove --tasks` reads tasks from a
+//! file, or from every task file in a directory, and proves them on the same pool and
+//! with the same programs as orchestrator tasks, without any network access. Each task
+//! is written to the output directory as the archive does: `<task>.proof`, `<task>.json`
+//! and `<task>.log`.
+//!
+//! A task file is either:
+//! - `.json`: one task, or an array of them, each with a `task_id`, hex-encoded
+//!   `public_inputs`, and either a registered `program_id` or the path of an `elf`
+//!   (relative to the file); a task naming neither proves the default program
+//! - `.pb`: a `GetProofTaskResponse`, as the orchestrator hands out tasks
+//!
+//! Files in a directory are read in name order, and tasks in file order, so a run is
+//! reproducible.
+//!
+//! `prove --elf` proves a single guest program the same way, on inputs given on the
+//! command line (see [`Input`]).
+
+use crate::archive;
+use crate::guest::{Input, Outcome};
+use crate::nexus_orchestrator::{GetProofTaskResponse, ProofEncoding};
+use crate::programs::{self, Program};
+use crate::proof_encoding::{self, EncodedProof};
+use crate::prover::ProofSummary;
+use crate::proving::ProverPool;
+use log::error;
+use prost::Message;
+use serde::Deserialize;
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+
+/// A task as written in a JSON task file
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct TaskEntry {
+    task_id: String,
+    #[serde(default)]
+    program_id: Option<String>,
+    #[serde(default)]
+    elf: Option<PathBuf>,
+    #[serde(default)]
+    public_inputs: String,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum TaskFile {
+    One(TaskEntry),
+    Many(Vec<TaskEntry>),
+}
+
+/// What a local task proves
+#[derive(Debug)]
+enum Guest {
+    Registered(&'static Program),
+    Elf(PathBuf),
+}
+
+#[derive(Debug)]
+pub struct LocalTask {
+    pub task_id: String,
+    guest: Guest,
+    public_inputs: Vec<u8>,
+    encoding: ProofEncoding,
+}
+
+impl LocalTask {
+    /// The registered program's ID, or the ELF's path
+    fn program_id(&self) -> String {
+        match &self.guest {
+            Guest::Registered(program) => program.id.to_string(),
+            Guest::Elf(path) => path.display().to_string(),
+        }
+    }
+}
+
+/// Read the tasks in a task file, or in every task file in a directory
+pub fn load(path: &Path) -> Result<Vec<LocalTask>, String> {
+    let mut files = if path.is_dir() {
+        let entries = std::fs::read_dir(path)
+            .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+        let mut files = Vec::new();
+        for entry in entries {
+            let file = entry
+                .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?
+                .path();
+            if matches!(extension(&file), Some("json" | "pb")) {
+                files.push(file);
+            }
+        }
+        files
+    } else {
+        vec![path.to_path_buf()]
+    };
+    files.sort();
+
+    let mut tasks = Vec::new();
+    for file in &files {
+        tasks.extend(load_file(file)?);
+    }
+
+    // Outputs are named after their task
+    let mut ids = HashSet::new();
+    if let Some(task) = tasks.iter().find(|task| !ids.insert(&task.task_id)) {
+        return Err(format!("Task {} appears more than once", task.task_id));
+    }
+    Ok(tasks)
+}
+
+fn extension(path: &Path) -> Option<&str> {
+    path.extension().and_then(|extension| extension.to_str())
+}
+
+fn load_file(path: &Path) -> Result<Vec<LocalTask>, String> {
+    let contents =
+        std::fs::read(path).map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+    let invalid =
+        |e: &dyn std::fmt::Display| format!("Invalid task file {}: {}", path.display(), e);
+
+    match extension(path) {
+        Some("pb") => {
+            let task =
+                GetProofTaskResponse::decode(contents.as_slice()).map_err(|e| invalid(&e))?;
+            let program = programs::for_task(&task.program_id).map_err(|e| invalid(&e))?;
+            Ok(vec![LocalTask {
+                task_id: task.task_id,
+                guest: Guest::Registered(program),
+                public_inputs: task.public_inputs,
+                encoding: proof_encoding::negotiate(task.proof_encoding),
+            }])
+        }
+        _ => {
+            let entries = match serde_json::from_slice(&contents).map_err(|e| invalid(&e))? {
+                TaskFile::One(entry) => vec![entry],
+                TaskFile::Many(entries) => entries,
+            };
+            let dir = path.parent().unwrap_or(Path::new("."));
+            entries
+                .into_iter()
+                .map(|entry| task_from_entry(entry, dir).map_err(|e| invalid(&e)))
+                .collect()
+        }
+    }
+}
+
+fn task_from_entry(entry: TaskEntry, dir: &Path) -> Result<LocalTask, String> {
+    let guest = match (entry.program_id, entry.elf) {
+        (Some(_), Some(_)) => {
+            return Err(format!(
+                "task {} names both a program and an ELF",
+                entry.task_id
+            ))
+        }
+        (Some(id), None) => Guest::Registered(
+            programs::find(&id).ok_or_else(|| format!("unknown program '{}'", id))?,
+        ),
+        (None, Some(elf)) => Guest::Elf(dir.join(elf)),
+        (None, None) => Guest::Registered(&programs::PROGRAMS[0]),
+    };
+    let public_inputs = hex::decode(entry.public_inputs.trim_start_matches("0x"))
+        .map_err(|e| format!("public inputs of task {} are not hex: {}", entry.task_id, e))?;
+
+    Ok(LocalTask {
+        task_id: entry.task_id,
+        guest,
+        public_inputs,
+        encoding: ProofEncoding::default(),
+    })
+}
+
+/// Prove every task in `tasks` and write the results to `output_dir`, printing a JSON
+/// summary of each. Fails if any task could not be proven or its guest did not succeed.
+pub async fn run(
+    tasks: Vec<LocalTask>,
+    output_dir: &Path,
+    pool: &ProverPool,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let total = tasks.len();
+    let mut failed = 0;
+    for task in tasks {
+        let task_id = task.task_id.clone();
+        match prove(task, output_dir, pool).await {
+            Ok((summary, outcome)) => {
+                println!("{}", serde_json::to_string_pretty(&summary)?);
+                if !outcome.is_success() {
+                    error!("Task {} failed: guest program {}", task_id, outcome);
+                    failed += 1;
+                }
+            }
+            Err(e) => {
+                error!("Task {} failed: {}", task_id, e);
+                failed += 1;
+            }
+        }
+    }
+
+    println!("{} of {} task(s) succeeded", total - failed, total);
+    if failed > 0 {
+        return Err(format!("{} task(s) failed", failed).into());
+    }
+    Ok(())
+}
+
+/// Prove an ELF on the given inputs, outside of any task, print a JSON summary, and
+/// write the proof to `output` if given. Fails if the guest did not succeed.
+pub async fn prove_elf(
+    elf: PathBuf,
+    private_input: Input,
+    public_input: Input,
+    output: Option<&Path>,
+    pool: &ProverPool,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let program_id = elf.display().to_string();
+    println!("Proving {}...", program_id);
+    let started = Instant::now();
+    let run = pool
+        .run(move |cancelled| programs::prove_elf(&elf, &private_input, &public_input, cancelled))
+        .await??;
+    let duration = started.elapsed();
+
+    let encoding = ProofEncoding::default();
+    let encoded_proof = match &run.proof {
+        Some(proof) => proof_encoding::encode(proof, encoding)?,
+        None => EncodedProof::empty(encoding),
+    };
+    if let (Some(output), Some(_)) = (output, &run.proof) {
+        std::fs::write(output, &encoded_proof.bytes)?;
+        println!("Wrote proof to {}", output.display());
+    }
+    let summary = ProofSummary::new(None, program_id, &run, &encoded_proof, duration);
+    println!("{}", serde_json::to_string_pretty(&summary)?);
+
+    if !run.outcome.is_success() {
+        return Err(format!("Guest program {}", run.outcome).into());
+    }
+    Ok(())
+}
+
+#[tracing::instrument(name = "proof_task", skip_all, fields(task_id = %task.task_id))]
+async fn prove(
+    task: LocalTask,
+    output_dir: &Path,
+    pool: &ProverPool,
+) -> Result<(ProofSummary, Outcome), Box<dyn std::error::Error>> {
+    let program_id = task.program_id();
+    println!("Proving task {} ({})...", task.task_id, program_id);
+    let span = tracing::Span::current();
+    let started = Instant::now();
+    let LocalTask {
+        task_id,
+        guest,
+        public_inputs,
+        encoding,
+    } = task;
+    let run = pool
+        .run(move |cancelled| {
+            span.in_scope(|| match &guest {
+                Guest::Registered(program) => program.prove(&public_inputs, cancelled),
+                Guest::Elf(path) => {
+                    let public_input = Input::Bytes(public_inputs.clone());
+                    programs::prove_elf(path, &Input::None, &public_input, cancelled)
+                }
+            })
+        })
+        .await??;
+    let duration = started.elapsed();
+
+    let encoded_proof = match &run.proof {
+        Some(proof) => tracing::info_span!("serialize_proof")
+            .in_scope(|| proof_encoding::encode(proof, encoding))?,
+        None => EncodedProof::empty(encoding),
+    };
+    archive::save(output_dir, &task_id, &encoded_proof, &run)?;
+
+    let summary = ProofSummary::new(Some(task_id), program_id, &run, &encoded_proof, duration);
+    Ok((summary, run.outcome))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tasks_are_read_from_json_and_protobuf_in_order() {
+        let dir = std::env::temp_dir().join(format!("nexus-tasks-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(
+            dir.join("a.json"),
+            r#"[
+                {"task_id": "fib", "program_id": "fib_input", "public_inputs": "09"},
+                {"task_id": "custom", "elf": "guest.elf", "public_inputs": "0x0102"}
+            ]"#,
+        )
+        .unwrap();
+        let queued = GetProofTaskResponse {
+            task_id: "queued".to_string(),
+            public_inputs: vec![3],
+            ..Default::default()
+        };
+        std::fs::write(dir.join("b.pb"), queued.encode_to_vec()).unwrap();
+        std::fs::write(dir.join("notes.txt"), "not a task").unwrap();
+
+        let tasks = load(&dir).unwrap();
+
+        let ids: Vec<_> = tasks.iter().map(|task| task.task_id.as_str()).collect();
+        assert_eq!(ids, ["fib", "custom", "queued"]);
+        assert_eq!(tasks[0].public_inputs, [9]);
+        assert_eq!(
+            tasks[1].program_id(),
+            dir.join("guest.elf").display().to_string()
+        );
+        assert_eq!(tasks[1].public_inputs, [1, 2]);
+        assert_eq!(tasks[2].program_id(), "fib_input");
+
+        // Outputs are named after tasks, so IDs must be unique
+        std::fs::write(dir.join("c.json"), r#"{"task_id": "fib"}"#).unwrap();
+        assert!(load(&dir).unwrap_err().contains("more than once"));
+        std::fs::write(
+            dir.join("c.json"),
+            r#"{"task_id": "x", "program_id": "nope"}"#,
+        )
+        .unwrap();
+        assert!(load(&dir).unwrap_err().contains("unknown program"));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,20 +1,38 @@
 // Copyright (c) 2024 Nexus. All rights reserved.
 
 mod analytics;
+mod archive;
+#[cfg(test)]
+mod benchmark;
 mod config;
+mod connection;
+#[cfg(test)]
+mod differential;
 // mod prover;
+mod doctor;
 mod flops;
+mod guest;
+mod lease;
+mod local_tasks;
 mod memory_stats;
+mod network;
 #[path = "proto/nexus.orchestrator.rs"]
 mod nexus_orchestrator;
 mod node_id_manager;
 mod orchestrator_client;
+mod programs;
+mod proof_encoding;
 mod prover;
+mod proving;
 mod setup;
+mod state;
+mod telemetry;
+mod tls;
 mod utils;
 
 // Update the import path to use the proto module
 use clap::{Parser, Subcommand};
+use utils::updater;
 
 #[derive(clap::ValueEnum, Clone, Debug)]
 enum Environment {
@@ -39,9 +57,352 @@ enum Command {
         /// Environment to run in
         #[arg(long, value_enum)]
         env: Option<Environment>,
+
+        /// How a connected node receives tasks from the orchestrator
+        #[arg(long, value_enum, default_value_t = connection::Protocol::Polling)]
+        protocol: connection::Protocol,
+
+        #[command(flatten)]
+        network: NetworkArgs,
+
+        #[command(flatten)]
+        tls: TlsArgs,
+
+        #[command(flatten)]
+        proving: ProvingArgs,
+
+        #[command(flatten)]
+        analytics: AnalyticsArgs,
+
+        #[command(flatten)]
+        tracing: TracingArgs,
+
+        #[command(flatten)]
+        updater: UpdaterArgs,
+    },
+    /// Prove tasks without the interactive setup, printing a JSON summary of each proof
+    Prove {
+        /// Environment to run in
+        #[arg(long, value_enum)]
+        env: Option<Environment>,
+
+        /// Exit after the first proof
+        #[arg(long)]
+        once: bool,
+
+        /// Prove the tasks in this file, or in the task files in this directory, instead
+        /// of tasks from the orchestrator
+        #[arg(
+            long,
+            value_name = "PATH",
+            requires = "output_dir",
+            conflicts_with = "once"
+        )]
+        tasks: Option<std::path::PathBuf>,
+
+        /// Where to write the proofs of local tasks and their results
+        #[arg(long, value_name = "DIR", requires = "tasks")]
+        output_dir: Option<std::path::PathBuf>,
+
+        /// Prove this guest program, built with nexus-rt, instead of a task
+        #[arg(long, value_name = "PATH", conflicts_with_all = ["once", "tasks"])]
+        elf: Option<std::path::PathBuf>,
+
+        /// Public input of the `--elf` program: JSON, 0x-prefixed hex, or @file
+        #[arg(long, value_name = "INPUT", requires = "elf")]
+        input: Option<guest::Input>,
+
+        /// Private input of the `--elf` program: JSON, 0x-prefixed hex, or @file
+        #[arg(long, value_name = "INPUT", requires = "elf")]
+        private_input: Option<guest::Input>,
+
+        /// Where to write the proof of the `--elf` program
+        #[arg(long, value_name = "PATH", requires = "elf")]
+        output: Option<std::path::PathBuf>,
+
+        #[command(flatten)]
+        network: NetworkArgs,
+
+        #[command(flatten)]
+        tls: TlsArgs,
+
+        #[command(flatten)]
+        proving: ProvingArgs,
+
+        #[command(flatten)]
+        analytics: AnalyticsArgs,
+
+        #[command(flatten)]
+        tracing: TracingArgs,
+    },
+    /// Check for a new release of the CLI, or install it
+    Update {
+        /// Whether to only report the available version or also install it
+        #[arg(value_enum, default_value_t = UpdateAction::Check)]
+        action: UpdateAction,
+
+        #[command(flatten)]
+        network: NetworkArgs,
+
+        #[command(flatten)]
+        updater: UpdaterArgs,
     },
     /// Logout from the current session
     Logout,
+    /// Show the effective configuration and what this node sends to Nexus
+    Doctor {
+        /// Environment to inspect
+        #[arg(long, value_enum)]
+        env: Option<Environment>,
+
+        #[command(flatten)]
+        network: NetworkArgs,
+
+        #[command(flatten)]
+        analytics: AnalyticsArgs,
+
+        #[command(flatten)]
+        tracing: TracingArgs,
+    },
+    /// Show how this binary was built
+    BuildInfo,
+    /// Prove a bundled program once; used by the updater before switching versions
+    #[command(hide = true)]
+    SelfCheck,
+    /// Run a proof the node hands over on stdin, writing the result to stdout
+    #[command(hide = true)]
+    ProveJob {
+        #[arg(long, value_enum, default_value_t)]
+        log_level: telemetry::LogLevel,
+    },
+}
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug)]
+enum UpdateAction {
+    /// Report whether a new version is available
+    Check,
+    /// Download, verify and install the new version; it runs from the next start. Fails if a
+    /// running node does not release the state directory within 10 seconds.
+    Apply,
+}
+
+/// Overrides for the `updater` section of the settings file
+#[derive(clap::Args, Debug)]
+struct UpdaterArgs {
+    /// How often to check for updates
+    #[arg(long, value_enum, default_value_t = updater::AutoUpdaterMode::Production)]
+    updater_mode: updater::AutoUpdaterMode,
+
+    /// Which releases to follow
+    #[arg(long, value_enum)]
+    update_channel: Option<updater::UpdateChannel>,
+
+    /// What to do when a new release is found
+    #[arg(long, value_enum)]
+    update_policy: Option<updater::UpdatePolicy>,
+
+    /// Run exactly this version instead of following a channel
+    #[arg(long)]
+    pin_version: Option<semver::Version>,
+}
+
+impl UpdaterArgs {
+    /// Apply the command-line overrides on top of the settings file
+    fn apply(&self, settings: &mut config::Settings) {
+        if let Some(channel) = self.update_channel {
+            settings.updater.channel = channel;
+        }
+        if let Some(policy) = self.update_policy {
+            settings.updater.policy = policy;
+        }
+        if let Some(version) = &self.pin_version {
+            settings.updater.pinned_version = Some(version.clone());
+        }
+    }
+}
+
+/// Overrides for the `tls` section of the settings file
+#[derive(clap::Args, Debug)]
+struct TlsArgs {
+    /// PEM file with the CA certificates to trust for the orchestrator
+    #[arg(long)]
+    ca_bundle: Option<std::path::PathBuf>,
+
+    /// Require the orchestrator's key to match this SPKI hash (`sha256/<base64>`); repeatable
+    #[arg(long = "pin-spki")]
+    spki_pins: Vec<String>,
+
+    /// Do not verify the orchestrator's certificate. Only for testing a local orchestrator.
+    #[arg(long, default_value_t = false)]
+    insecure: bool,
+}
+
+impl TlsArgs {
+    /// Apply the command-line overrides on top of the settings file
+    fn apply(&self, settings: &mut config::Settings) {
+        if let Some(path) = &self.ca_bundle {
+            settings.tls.ca_bundle = Some(path.clone());
+        }
+        if !self.spki_pins.is_empty() {
+            settings.tls.spki_pins = self.spki_pins.clone();
+        }
+        if self.insecure {
+            settings.tls.insecure = true;
+        }
+    }
+}
+
+/// Overrides for the `proving` section of the settings file
+#[derive(clap::Args, Debug)]
+struct ProvingArgs {
+    /// Seconds a single proof may take before it is abandoned
+    #[arg(long, value_name = "SECONDS")]
+    proof_timeout: Option<u64>,
+
+    /// Keep a copy of every submitted proof and its guest output in this directory
+    #[arg(long, value_name = "DIR")]
+    archive_dir: Option<std::path::PathBuf>,
+
+    /// Attach what the guest program printed to submissions
+    #[arg(long, default_value_t = false)]
+    attach_guest_output: bool,
+}
+
+impl ProvingArgs {
+    /// Apply the command-line overrides on top of the settings file
+    fn apply(&self, settings: &mut config::Settings) {
+        if let Some(secs) = self.proof_timeout {
+            settings.proving.timeout_secs = secs;
+        }
+        if let Some(dir) = &self.archive_dir {
+            settings.proving.archive_dir = Some(dir.clone());
+        }
+        if self.attach_guest_output {
+            settings.proving.attach_guest_output = true;
+        }
+    }
+}
+
+/// Overrides for the `analytics` section of the settings file
+#[derive(clap::Args, Debug)]
+struct AnalyticsArgs {
+    /// Do not record any analytics events
+    #[arg(long, default_value_t = false)]
+    no_analytics: bool,
+
+    /// Where to deliver analytics events
+    #[arg(long, value_enum)]
+    analytics_sink: Option<analytics::SinkKind>,
+
+    /// How to treat the node ID and location fields in analytics events
+    #[arg(long, value_enum)]
+    analytics_redaction: Option<analytics::Redaction>,
+}
+
+impl AnalyticsArgs {
+    /// Apply the command-line overrides on top of the settings file
+    fn apply(&self, settings: &mut config::Settings) {
+        if self.no_analytics {
+            settings.analytics.enabled = false;
+        }
+        if let Some(sink) = self.analytics_sink {
+            settings.analytics.sink = sink;
+        }
+        if let Some(redaction) = self.analytics_redaction {
+            settings.analytics.redaction = redaction;
+        }
+    }
+}
+
+/// Overrides for the `network` section of the settings file
+#[derive(clap::Args, Debug)]
+struct NetworkArgs {
+    /// Proxy for all connections: `http://`, `https://`, `socks5://` or `socks5h://` URL
+    #[arg(long)]
+    proxy: Option<String>,
+
+    /// Comma-separated hosts, domains, IPs or CIDR ranges to reach without the proxy
+    #[arg(long)]
+    no_proxy: Option<String>,
+
+    /// Seconds to wait for a connection to be established
+    #[arg(long, value_name = "SECONDS")]
+    connect_timeout: Option<u64>,
+
+    /// Seconds a request may go without receiving data
+    #[arg(long, value_name = "SECONDS")]
+    read_timeout: Option<u64>,
+
+    /// Seconds allowed for uploading a proof
+    #[arg(long, value_name = "SECONDS")]
+    upload_timeout: Option<u64>,
+
+    /// Local IP address to connect from
+    #[arg(long)]
+    source_address: Option<std::net::IpAddr>,
+
+    /// Network interface to connect through (Linux only)
+    #[arg(long)]
+    interface: Option<String>,
+}
+
+impl NetworkArgs {
+    /// Apply the command-line overrides on top of the settings file
+    fn apply(&self, settings: &mut config::Settings) {
+        let network = &mut settings.network;
+        if let Some(proxy) = &self.proxy {
+            network.proxy = Some(proxy.clone());
+        }
+        if let Some(no_proxy) = &self.no_proxy {
+            network.no_proxy = Some(no_proxy.clone());
+        }
+        if let Some(secs) = self.connect_timeout {
+            network.connect_timeout_secs = secs;
+        }
+        if let Some(secs) = self.read_timeout {
+            network.read_timeout_secs = secs;
+        }
+        if let Some(secs) = self.upload_timeout {
+            network.upload_timeout_secs = secs;
+        }
+        if let Some(address) = self.source_address {
+            network.source_address = Some(address);
+        }
+        if let Some(interface) = &self.interface {
+            network.interface = Some(interface.clone());
+        }
+    }
+}
+
+/// Overrides for the `tracing` section of the settings file
+#[derive(clap::Args, Debug)]
+struct TracingArgs {
+    /// Where to export OpenTelemetry spans for the fetch, prove and submit steps
+    #[arg(long, value_enum)]
+    trace_exporter: Option<telemetry::TraceExporter>,
+
+    /// OpenTelemetry collector URL for the `otlp` exporter
+    #[arg(long)]
+    otlp_endpoint: Option<String>,
+
+    /// Lowest level of log messages to print; `debug` includes guest program output
+    #[arg(long, value_enum)]
+    log_level: Option<telemetry::LogLevel>,
+}
+
+impl TracingArgs {
+    /// Apply the command-line overrides on top of the settings file
+    fn apply(&self, settings: &mut config::Settings) {
+        if let Some(exporter) = self.trace_exporter {
+            settings.tracing.exporter = exporter;
+        }
+        if let Some(endpoint) = &self.otlp_endpoint {
+            settings.tracing.otlp_endpoint = Some(endpoint.clone());
+        }
+        if let Some(level) = self.log_level {
+            settings.tracing.log_level = level;
+        }
+    }
 }
 
 #[derive(Parser, Debug)]
@@ -61,19 +422,176 @@ struct Args {
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
     let cli = Cli::parse();
+    let mut settings = config::Settings::load();
 
     //each arm of the match is a command
     match cli.command {
-        Command::Start { env } => {
-            match prover::start_prover(&config::Environment::from_args(env.as_ref())).await {
-                Ok(_) => println!("Prover started successfully"),
-                Err(e) => eprintln!("Failed to start prover: {}", e),
+        Command::Start {
+            env,
+            protocol,
+            network,
+            tls: tls_args,
+            proving: proving_args,
+            analytics,
+            tracing,
+            updater: updater_args,
+        } => {
+            let environment = config::Environment::from_args(env.as_ref());
+            network.apply(&mut settings);
+            tls_args.apply(&mut settings);
+            proving_args.apply(&mut settings);
+            analytics.apply(&mut settings);
+            tracing.apply(&mut settings);
+            updater_args.apply(&mut settings);
+            let _state_lock = state::StateLock::acquire()?;
+            updater::check_pending_update()?;
+            analytics::init(&settings.analytics, &settings.network, &environment);
+            let tracer_provider = telemetry::init(&settings.tracing)?;
+            let tls_config = tls::client_config(&settings.tls)?;
+            match updater::UpdaterConfig::new(
+                updater_args.updater_mode,
+                &settings.updater,
+                &settings.network,
+            ) {
+                Ok(config) => updater::spawn_background_updater(config)?,
+                Err(e) => eprintln!(
+                    "{}[auto-updater]{} {}; automatic updates are disabled",
+                    updater::BLUE,
+                    updater::RESET,
+                    e
+                ),
+            }
+
+            let pool = std::sync::Arc::new(proving::ProverPool::new(&settings.proving));
+            proving::spawn_abort_listener(pool.clone());
+
+            tokio::select! {
+                result = prover::start_prover(&environment, protocol, &settings.network, tls_config, pool.clone(), &settings.proving) => match result {
+                    Ok(_) => println!("Prover started successfully"),
+                    Err(e) => eprintln!("Failed to start prover: {}", e),
+                },
+                _ = tokio::signal::ctrl_c() => println!("\nShutting down..."),
+            }
+            // Stop any proof still running instead of waiting for it
+            pool.shutdown();
+
+            telemetry::shutdown(tracer_provider);
+            analytics::shutdown(std::time::Duration::from_secs(5)).await;
+        }
+        Command::Prove {
+            env,
+            once,
+            tasks,
+            output_dir,
+            elf,
+            input,
+            private_input,
+            output,
+            network,
+            tls: tls_args,
+            proving: proving_args,
+            analytics,
+            tracing,
+        } => {
+            let environment = config::Environment::from_args(env.as_ref());
+            network.apply(&mut settings);
+            tls_args.apply(&mut settings);
+            proving_args.apply(&mut settings);
+            analytics.apply(&mut settings);
+            tracing.apply(&mut settings);
+            analytics::init(&settings.analytics, &settings.network, &environment);
+            let tracer_provider = telemetry::init(&settings.tracing)?;
+
+            let pool = std::sync::Arc::new(proving::ProverPool::new(&settings.proving));
+            proving::spawn_abort_listener(pool.clone());
+
+            let result = match (tasks, output_dir, elf) {
+                (_, _, Some(elf)) => {
+                    tokio::select! {
+                        result = local_tasks::prove_elf(elf, private_input.unwrap_or_default(), input.unwrap_or_default(), output.as_deref(), &pool) => result,
+                        _ = tokio::signal::ctrl_c() => Ok(()),
+                    }
+                }
+                (Some(tasks), Some(output_dir), None) => {
+                    let tasks = local_tasks::load(&tasks)?;
+                    tokio::select! {
+                        result = local_tasks::run(tasks, &output_dir, &pool) => result,
+                        _ = tokio::signal::ctrl_c() => Ok(()),
+                    }
+                }
+                _ => {
+                    let tls_config = tls::client_config(&settings.tls)?;
+                    let node_id = setup::saved_node_id();
+                    tokio::select! {
+                        result = prover::prove_tasks(&environment, node_id.as_deref(), &settings.network, tls_config, &pool, &settings.proving, once) => result,
+                        _ = tokio::signal::ctrl_c() => Ok(()),
+                    }
+                }
+            };
+            pool.shutdown();
+            telemetry::shutdown(tracer_provider);
+            analytics::shutdown(std::time::Duration::from_secs(5)).await;
+            result?;
+        }
+        Command::Update {
+            action,
+            network,
+            updater: updater_args,
+        } => {
+            network.apply(&mut settings);
+            updater_args.apply(&mut settings);
+            let config = updater::UpdaterConfig::new(
+                updater_args.updater_mode,
+                &settings.updater,
+                &settings.network,
+            )?;
+            let manager = updater::VersionManager::new(config)?;
+
+            match (manager.update_version_status().await?, action) {
+                (updater::VersionStatus::UpToDate, _) => {
+                    println!("Version {} is up to date", manager.current_version())
+                }
+                (updater::VersionStatus::UpdateAvailable(version), UpdateAction::Check) => {
+                    println!(
+                        "Version {} is available (running {})",
+                        version,
+                        manager.current_version()
+                    )
+                }
+                (updater::VersionStatus::UpdateAvailable(version), UpdateAction::Apply) => {
+                    let _state_lock = state::StateLock::acquire()?;
+                    manager.install_update(&version).await?;
+                    println!("Installed version {}; restart the node to run it", version);
+                }
             }
         }
         Command::Logout => match setup::clear_node_id() {
             Ok(_) => println!("Successfully logged out"),
             Err(e) => eprintln!("Failed to logout: {}", e),
         },
+        Command::Doctor {
+            env,
+            network,
+            analytics,
+            tracing,
+        } => {
+            let environment = config::Environment::from_args(env.as_ref());
+            network.apply(&mut settings);
+            analytics.apply(&mut settings);
+            tracing.apply(&mut settings);
+
+            doctor::run(&environment, &settings);
+        }
+        Command::BuildInfo => doctor::print_build_info(),
+        Command::SelfCheck => prover::self_check()?,
+        Command::ProveJob { log_level } => {
+            // Spans are not exported: stdout carries the result
+            let _ = telemetry::init(&telemetry::TracingSettings {
+                log_level,
+                ..Default::default()
+            })?;
+            programs::serve_job()?;
+        }
     }
 
     Ok(())
@@ -9,7 +9,6 @@ pub fn bytes_to_mb_i32(bytes: u64) -> i32 {
 }
 
 // At server, we decode the memory usage from i32 to f32 to get correct memory usage
-#[allow(dead_code)]
 pub fn mb_i32_to_f32(mb: i32) -> f32 {
     // Convert back to f32, dividing by 1000 to get the correct value
     (mb as f32) / 1000.0
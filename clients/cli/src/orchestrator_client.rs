@@ -1,36 +1,99 @@
 use crate::config;
 use crate::flops::measure_flops;
 use crate::memory_stats::get_memory_info;
+use crate::network::{self, NetworkSettings};
 use crate::nexus_orchestrator::{
-    GetProofTaskRequest, GetProofTaskResponse, NodeType, SubmitProofRequest,
+    CommitProofUploadRequest, GetProofTaskRequest, GetProofTaskResponse, GuestOutcome,
+    LeaseReleaseReason, NodeTelemetry, NodeType, ReleaseLeaseRequest, RenewLeaseRequest,
+    RenewLeaseResponse, StartProofUploadRequest, StartProofUploadResponse, SubmitProofRequest,
+    UploadProofChunkRequest, UploadProofChunkResponse,
 };
+use crate::proof_encoding::{self, EncodedProof};
 use prost::Message;
-use reqwest::{Client, ClientBuilder};
+use reqwest::Client;
+use std::sync::Arc;
 use std::time::Duration;
 
+/// Size of each piece of a chunked upload; smaller proofs are sent in one request
+const UPLOAD_CHUNK_SIZE: usize = 1024 * 1024;
+/// Failed requests in a row after which a chunked upload is abandoned
+const MAX_UPLOAD_FAILURES: u32 = 5;
+
+/// The orchestrator answered a request with an error status
+#[derive(Debug)]
+struct StatusError {
+    status: u16,
+    message: String,
+}
+
+impl std::fmt::Display for StatusError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl std::error::Error for StatusError {}
+
+/// The orchestrator has no endpoint for starting a chunked upload
+#[derive(Debug)]
+struct ChunkedUploadsUnsupported;
+
+impl std::fmt::Display for ChunkedUploadsUnsupported {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "The orchestrator does not accept chunked uploads")
+    }
+}
+
+impl std::error::Error for ChunkedUploadsUnsupported {}
+
+/// HTTP status of a failed request, if the orchestrator answered at all
+fn error_status(error: &(dyn std::error::Error + 'static)) -> Option<u16> {
+    error.downcast_ref::<StatusError>().map(|e| e.status)
+}
+
+/// What the node reports about a task along with its proof
+#[derive(Debug, Clone, Default)]
+pub struct TaskReport {
+    /// How the guest program ended; absent means it succeeded
+    pub guest_outcome: Option<GuestOutcome>,
+    /// What the guest printed, if the node shares it
+    pub guest_output: Option<String>,
+    /// The guest's public output as JSON, if it committed one
+    pub public_output: Option<String>,
+}
+
 pub struct OrchestratorClient {
     client: Client,
     base_url: String,
+    upload_timeout: Duration,
+    upload_chunk_size: usize,
     // environment: config::Environment,
 }
 
 impl OrchestratorClient {
-    pub fn new(environment: config::Environment) -> Self {
-        Self {
-            client: ClientBuilder::new()
-                .timeout(Duration::from_secs(10))
-                .build()
-                .expect("Failed to create HTTP client"),
+    pub fn new(
+        environment: config::Environment,
+        network: &NetworkSettings,
+        tls_config: &Arc<rustls::ClientConfig>,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        Ok(Self {
+            client: network::client_builder(network)?
+                .use_preconfigured_tls(rustls::ClientConfig::clone(tls_config))
+                .build()?,
             base_url: environment.orchestrator_url(),
+            upload_timeout: network.upload_timeout(),
+            upload_chunk_size: UPLOAD_CHUNK_SIZE,
             // environment,
-        }
+        })
     }
 
+    /// Send a request, optionally bounding its total duration by `timeout`
     async fn make_request<T, U>(
         &self,
         url: &str,
         method: &str,
         request_data: &T,
+        timeout: Option<Duration>,
     ) -> Result<Option<U>, Box<dyn std::error::Error>>
     where
         T: Message,
@@ -42,24 +105,26 @@ impl OrchestratorClient {
         let friendly_connection_error =
             "[CONNECTION] Unable to reach server. The service might be temporarily unavailable."
                 .to_string();
-        let friendly_messages = match method {
-            "POST" => match self
+        let request = match method {
+            "POST" => self
                 .client
                 .post(&url)
                 .header("Content-Type", "application/octet-stream")
-                .body(request_bytes)
-                .send()
-                .await
-            {
-                Ok(resp) => resp,
-                Err(_) => return Err(friendly_connection_error.into()),
-            },
-            "GET" => match self.client.get(&url).send().await {
-                Ok(resp) => resp,
-                Err(_) => return Err(friendly_connection_error.into()),
-            },
+                .body(request_bytes),
+            "GET" => self.client.get(&url),
             _ => return Err("[METHOD] Unsupported HTTP method".into()),
         };
+        let request = match timeout {
+            Some(timeout) => request.timeout(timeout),
+            None => request,
+        };
+        let friendly_messages = match request.send().await {
+            Ok(resp) => resp,
+            Err(e) if e.is_timeout() => {
+                return Err("[TIMEOUT] The request to the server timed out.".into())
+            }
+            Err(_) => return Err(friendly_connection_error.into()),
+        };
 
         if !friendly_messages.status().is_success() {
             let status = friendly_messages.status();
@@ -85,7 +150,10 @@ impl OrchestratorClient {
                 _ => format!("[{}] Unexpected error: {}", status, clean_error),
             };
 
-            return Err(friendly_message.into());
+            return Err(Box::new(StatusError {
+                status: status.as_u16(),
+                message: friendly_message,
+            }));
         }
 
         let response_bytes = friendly_messages.bytes().await?;
@@ -102,6 +170,7 @@ impl OrchestratorClient {
         }
     }
 
+    #[tracing::instrument(skip(self))]
     pub async fn get_proof_task(
         &self,
         node_id: &str,
@@ -109,41 +178,461 @@ impl OrchestratorClient {
         let request = GetProofTaskRequest {
             node_id: node_id.to_string(),
             node_type: NodeType::CliProver as i32,
+            supported_serializations: proof_encoding::SUPPORTED_SERIALIZATIONS
+                .map(|serialization| serialization as i32)
+                .to_vec(),
+            supported_compressions: proof_encoding::SUPPORTED_COMPRESSIONS
+                .map(|compression| compression as i32)
+                .to_vec(),
         };
 
         let response = self
-            .make_request("/tasks", "POST", &request)
+            .make_request("/tasks", "POST", &request, None)
             .await?
             .ok_or("No response received from get_proof_task")?;
 
         Ok(response)
     }
 
+    /// Extend the lease on a task, returning its new expiry in milliseconds since the Unix epoch
+    #[tracing::instrument(skip(self))]
+    pub async fn renew_lease(
+        &self,
+        node_id: &str,
+        task_id: &str,
+    ) -> Result<u64, Box<dyn std::error::Error>> {
+        let request = RenewLeaseRequest {
+            node_id: node_id.to_string(),
+            task_id: task_id.to_string(),
+        };
+
+        let response: RenewLeaseResponse = self
+            .make_request("/tasks/lease/renew", "POST", &request, None)
+            .await?
+            .ok_or("No response received from renew_lease")?;
+
+        Ok(response.lease_expires_at_ms)
+    }
+
+    /// Give up a task so the orchestrator can hand it to another node
+    #[tracing::instrument(skip(self))]
+    pub async fn release_lease(
+        &self,
+        node_id: &str,
+        task_id: &str,
+        reason: LeaseReleaseReason,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let request = ReleaseLeaseRequest {
+            node_id: node_id.to_string(),
+            task_id: task_id.to_string(),
+            reason: reason as i32,
+        };
+
+        self.make_request::<ReleaseLeaseRequest, ()>(
+            "/tasks/lease/release",
+            "POST",
+            &request,
+            None,
+        )
+        .await?;
+
+        Ok(())
+    }
+
+    /// Submit a proof and what is known about the guest's run, in chunks if the proof
+    /// is too large for a single request
+    #[tracing::instrument(skip(self, proof, report), fields(proof_size = proof.bytes.len()))]
     pub async fn submit_proof(
         &self,
         task_id: &str,
-        proof_hash: &str,
-        proof: Vec<u8>,
+        proof: EncodedProof,
+        report: TaskReport,
     ) -> Result<(), Box<dyn std::error::Error>> {
-        let (program_memory, total_memory) = get_memory_info();
-        let flops = measure_flops();
+        if proof.bytes.len() <= self.upload_chunk_size {
+            return self.submit_proof_at_once(task_id, proof, report).await;
+        }
+
+        match self.upload_proof(task_id, &proof, &report).await {
+            // Orchestrators without chunked uploads still take the proof in one piece
+            Err(e) if e.is::<ChunkedUploadsUnsupported>() => {
+                self.submit_proof_at_once(task_id, proof, report).await
+            }
+            result => result,
+        }
+    }
 
+    async fn submit_proof_at_once(
+        &self,
+        task_id: &str,
+        proof: EncodedProof,
+        report: TaskReport,
+    ) -> Result<(), Box<dyn std::error::Error>> {
         let request = SubmitProofRequest {
             task_id: task_id.to_string(),
             node_type: NodeType::CliProver as i32,
-            proof_hash: proof_hash.to_string(),
-            proof,
-            node_telemetry: Some(crate::nexus_orchestrator::NodeTelemetry {
-                flops_per_sec: Some(flops as i32),
-                memory_used: Some(program_memory),
-                memory_capacity: Some(total_memory),
-                location: Some("US".to_string()),
-            }),
+            proof_hash: proof.hash,
+            proof: proof.bytes,
+            node_telemetry: Some(node_telemetry()),
+            proof_encoding: Some(proof.encoding),
+            guest_outcome: report.guest_outcome,
+            guest_output: report.guest_output,
+            public_output: report.public_output,
         };
 
-        self.make_request::<SubmitProofRequest, ()>("/tasks/submit", "POST", &request)
-            .await?;
+        self.make_request::<SubmitProofRequest, ()>(
+            "/tasks/submit",
+            "POST",
+            &request,
+            Some(self.upload_timeout),
+        )
+        .await?;
 
         Ok(())
     }
+
+    /// Upload a proof in chunks, resuming where the orchestrator left off after a failure.
+    ///
+    /// Every (re)start asks the orchestrator how much it has received, so chunks that
+    /// arrived without being acknowledged are not sent twice. The orchestrator checks
+    /// the assembled proof against its hash when the upload is committed.
+    async fn upload_proof(
+        &self,
+        task_id: &str,
+        proof: &EncodedProof,
+        report: &TaskReport,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let start = StartProofUploadRequest {
+            task_id: task_id.to_string(),
+            node_type: NodeType::CliProver as i32,
+            proof_size: proof.bytes.len() as u64,
+            proof_hash: proof.hash.clone(),
+            proof_encoding: Some(proof.encoding),
+        };
+        let mut failures = 0;
+
+        loop {
+            match self.upload_remaining_chunks(&start, proof).await {
+                Ok(upload_id) => {
+                    let commit = CommitProofUploadRequest {
+                        upload_id,
+                        proof_hash: proof.hash.clone(),
+                        node_telemetry: Some(node_telemetry()),
+                        guest_outcome: report.guest_outcome.clone(),
+                        guest_output: report.guest_output.clone(),
+                        public_output: report.public_output.clone(),
+                    };
+                    self.make_request::<CommitProofUploadRequest, ()>(
+                        "/tasks/upload/commit",
+                        "POST",
+                        &commit,
+                        Some(self.upload_timeout),
+                    )
+                    .await?;
+                    return Ok(());
+                }
+                Err((e, progressed)) => {
+                    // Requests the orchestrator rejected outright will not succeed on retry
+                    let status = error_status(e.as_ref());
+                    if e.is::<ChunkedUploadsUnsupported>()
+                        || matches!(status, Some(400..=499))
+                            && !matches!(status, Some(408 | 409 | 429))
+                    {
+                        return Err(e);
+                    }
+                    failures = if progressed { 1 } else { failures + 1 };
+                    if failures >= MAX_UPLOAD_FAILURES {
+                        return Err(format!(
+                            "Proof upload failed {} times in a row: {}",
+                            failures, e
+                        )
+                        .into());
+                    }
+
+                    let delay = Duration::from_secs(1 << (failures - 1));
+                    eprintln!(
+                        "Proof upload interrupted ({}); resuming in {}s...",
+                        e,
+                        delay.as_secs()
+                    );
+                    tokio::time::sleep(delay).await;
+                }
+            }
+        }
+    }
+
+    /// Start or resume an upload and send every chunk the orchestrator is missing.
+    ///
+    /// Returns the upload ID once all bytes are acknowledged. On failure, also says
+    /// whether any chunk was acknowledged before it.
+    async fn upload_remaining_chunks(
+        &self,
+        start: &StartProofUploadRequest,
+        proof: &EncodedProof,
+    ) -> Result<String, (Box<dyn std::error::Error>, bool)> {
+        let session: StartProofUploadResponse = self
+            .make_request("/tasks/upload", "POST", start, None)
+            .await
+            .and_then(|response| {
+                response.ok_or_else(|| "No response received from upload start".into())
+            })
+            .map_err(|e| match error_status(e.as_ref()) {
+                // Only a missing start endpoint means chunked uploads are not supported;
+                // a 404 later on is about this upload
+                Some(404) => (ChunkedUploadsUnsupported.into(), false),
+                _ => (e, false),
+            })?;
+
+        let total = proof.bytes.len();
+        let chunk_size = match session.max_chunk_size as usize {
+            0 => self.upload_chunk_size,
+            max => max.min(self.upload_chunk_size),
+        };
+        let mut offset = (session.received_size as usize).min(total);
+        if offset > 0 {
+            println!("Resuming proof upload at byte {} of {}", offset, total);
+        }
+
+        let mut progressed = false;
+        while offset < total {
+            let end = (offset + chunk_size).min(total);
+            let chunk = UploadProofChunkRequest {
+                upload_id: session.upload_id.clone(),
+                offset: offset as u64,
+                data: proof.bytes[offset..end].to_vec(),
+            };
+            let acknowledged: UploadProofChunkResponse = self
+                .make_request(
+                    "/tasks/upload/chunk",
+                    "POST",
+                    &chunk,
+                    Some(self.upload_timeout),
+                )
+                .await
+                .map(Option::unwrap_or_default)
+                .map_err(|e| (e, progressed))?;
+
+            // The orchestrator's count is authoritative; it may already have had this chunk
+            let received = acknowledged.received_size as usize;
+            if received <= offset || received > total {
+                return Err((
+                    format!(
+                        "Orchestrator acknowledged {} bytes after a chunk at offset {}",
+                        received, offset
+                    )
+                    .into(),
+                    progressed,
+                ));
+            }
+            offset = received;
+            progressed = true;
+        }
+
+        Ok(session.upload_id)
+    }
+}
+
+/// Performance stats reported with every submitted proof
+fn node_telemetry() -> NodeTelemetry {
+    let (program_memory, total_memory) = get_memory_info();
+    let flops = measure_flops();
+
+    NodeTelemetry {
+        flops_per_sec: Some(flops as i32),
+        memory_used: Some(program_memory),
+        memory_capacity: Some(total_memory),
+        location: Some("US".to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::nexus_orchestrator::{ProofCompression, ProofEncoding, ProofSerialization};
+    use std::sync::Mutex;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::{TcpListener, TcpStream};
+
+    /// What the mock orchestrator has seen of an upload
+    #[derive(Default)]
+    struct Upload {
+        starts: u32,
+        received: Vec<u8>,
+        committed_hash: Option<String>,
+        /// Store the next chunk, then drop the connection without answering
+        drop_after_chunk: Option<usize>,
+        /// Answer the start of an upload with this status instead
+        start_status: Option<u16>,
+        /// Answer the commit with this status instead
+        commit_status: Option<u16>,
+        /// Proofs submitted in one request
+        submitted: Vec<Vec<u8>>,
+    }
+
+    async fn read_request(stream: &mut TcpStream) -> (String, Vec<u8>) {
+        let mut head = Vec::new();
+        while !head.ends_with(b"\r\n\r\n") {
+            head.push(stream.read_u8().await.unwrap());
+        }
+        let head = String::from_utf8(head).unwrap().to_ascii_lowercase();
+        let length = head
+            .lines()
+            .find_map(|line| line.strip_prefix("content-length:"))
+            .map_or(0, |length| length.trim().parse().unwrap());
+        let mut body = vec![0u8; length];
+        stream.read_exact(&mut body).await.unwrap();
+        let path = head.split_whitespace().nth(1).unwrap().to_string();
+        (path, body)
+    }
+
+    /// Mock orchestrator implementing the chunked upload endpoints
+    async fn mock_orchestrator(upload: Arc<Mutex<Upload>>) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let base_url = format!("http://{}", listener.local_addr().unwrap());
+
+        tokio::spawn(async move {
+            while let Ok((mut stream, _)) = listener.accept().await {
+                let (path, body) = read_request(&mut stream).await;
+                let reply = {
+                    let mut upload = upload.lock().unwrap();
+                    match path.as_str() {
+                        "/v3/tasks/upload" if upload.start_status.is_some() => {
+                            Some((upload.start_status.unwrap(), Vec::new()))
+                        }
+                        "/v3/tasks/upload" => {
+                            upload.starts += 1;
+                            Some((
+                                200,
+                                StartProofUploadResponse {
+                                    upload_id: "upload-1".to_string(),
+                                    received_size: upload.received.len() as u64,
+                                    max_chunk_size: 0,
+                                }
+                                .encode_to_vec(),
+                            ))
+                        }
+                        "/v3/tasks/upload/chunk" => {
+                            let chunk = UploadProofChunkRequest::decode(body.as_slice()).unwrap();
+                            assert_eq!(chunk.offset as usize, upload.received.len());
+                            upload.received.extend(chunk.data);
+                            if upload.drop_after_chunk == Some(upload.received.len()) {
+                                upload.drop_after_chunk = None;
+                                None
+                            } else {
+                                Some((
+                                    200,
+                                    UploadProofChunkResponse {
+                                        received_size: upload.received.len() as u64,
+                                    }
+                                    .encode_to_vec(),
+                                ))
+                            }
+                        }
+                        "/v3/tasks/upload/commit" if upload.commit_status.is_some() => {
+                            Some((upload.commit_status.unwrap(), Vec::new()))
+                        }
+                        "/v3/tasks/upload/commit" => {
+                            let commit = CommitProofUploadRequest::decode(body.as_slice()).unwrap();
+                            upload.committed_hash = Some(commit.proof_hash);
+                            Some((200, Vec::new()))
+                        }
+                        "/v3/tasks/submit" => {
+                            let submit = SubmitProofRequest::decode(body.as_slice()).unwrap();
+                            upload.submitted.push(submit.proof);
+                            Some((200, Vec::new()))
+                        }
+                        other => panic!("unexpected request to {}", other),
+                    }
+                };
+
+                // No reply simulates a connection lost after the chunk arrived
+                if let Some((status, reply)) = reply {
+                    let head = format!(
+                        "HTTP/1.1 {} Status\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+                        status,
+                        reply.len()
+                    );
+                    let _ = stream.write_all(&[head.into_bytes(), reply].concat()).await;
+                }
+            }
+        });
+
+        base_url
+    }
+
+    fn test_client(base_url: String) -> OrchestratorClient {
+        OrchestratorClient {
+            client: Client::new(),
+            base_url,
+            upload_timeout: Duration::from_secs(5),
+            upload_chunk_size: 1000,
+        }
+    }
+
+    fn test_proof(len: usize) -> EncodedProof {
+        let bytes: Vec<u8> = (0..len).map(|i| (i % 251) as u8).collect();
+        proof_encoding::encode(
+            &bytes,
+            ProofEncoding {
+                serialization: ProofSerialization::Bincode as i32,
+                compression: ProofCompression::None as i32,
+            },
+        )
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_interrupted_upload_resumes_from_acknowledged_bytes() {
+        let upload = Arc::new(Mutex::new(Upload {
+            // The reply to the second chunk is lost
+            drop_after_chunk: Some(2000),
+            ..Default::default()
+        }));
+        let client = test_client(mock_orchestrator(upload.clone()).await);
+        let proof = test_proof(4500);
+
+        client
+            .submit_proof("task-1", proof.clone(), TaskReport::default())
+            .await
+            .unwrap();
+
+        let upload = upload.lock().unwrap();
+        // Resuming started the upload again, and the second chunk was not sent twice
+        assert_eq!(upload.starts, 2);
+        assert_eq!(upload.received, proof.bytes);
+        assert_eq!(upload.committed_hash.as_deref(), Some(proof.hash.as_str()));
+    }
+
+    #[tokio::test]
+    async fn test_proofs_are_sent_at_once_when_chunked_uploads_are_unsupported() {
+        let upload = Arc::new(Mutex::new(Upload {
+            start_status: Some(404),
+            ..Default::default()
+        }));
+        let client = test_client(mock_orchestrator(upload.clone()).await);
+        let proof = test_proof(4500);
+
+        client
+            .submit_proof("task-1", proof.clone(), TaskReport::default())
+            .await
+            .unwrap();
+
+        assert_eq!(upload.lock().unwrap().submitted, vec![proof.bytes]);
+    }
+
+    #[tokio::test]
+    async fn test_failed_commit_does_not_resubmit_the_proof_at_once() {
+        let upload = Arc::new(Mutex::new(Upload {
+            // The upload expired on the orchestrator before it was committed
+            commit_status: Some(404),
+            ..Default::default()
+        }));
+        let client = test_client(mock_orchestrator(upload.clone()).await);
+
+        let result = client
+            .submit_proof("task-1", test_proof(4500), TaskReport::default())
+            .await;
+
+        assert_eq!(error_status(result.unwrap_err().as_ref()), Some(404));
+        assert!(upload.lock().unwrap().submitted.is_empty());
+    }
 }
@@ -0,0 +1,426 @@
+//! Guest programs this node can prove.
+//!
+//! The orchestrator names each task's program by ID. A registered program knows where
+//! its ELF is, how to read a task's public inputs, and the type of the public output
+//! its guest commits, so that output can be decoded and reported with the proof.
+//!
+//! The ELFs are built from the examples crate by `scripts/build-guests.sh`, which records
+//! their hashes in `assets/manifest.json`. A bundled ELF is only proven if it still
+//! matches the manifest.
+//!
+//! Proofs run in a child process, which the node can kill to stop one (see
+//! [`crate::proving::run_child`]). The node hands the child a [`Job`] and reads back
+//! its [`GuestRun`].
+
+use crate::guest::{self, GuestRun, Input};
+use crate::proving;
+use nexus_sdk::{stwo::seq::Stwo, Local, Prover};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use sha3::Keccak256;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use tokio_util::sync::CancellationToken;
+use tracing::info_span;
+
+/// Proves a program on a task's public inputs, tracing the given ELF if any (see
+/// [`guest::prove`])
+type RunFn = fn(Stwo<Local>, Option<&[u8]>, &[u8]) -> Result<GuestRun, String>;
+
+/// A guest program and how to run it
+#[derive(Debug)]
+pub struct Program {
+    /// ID the orchestrator uses for the program
+    pub id: &'static str,
+    /// File name of the program's ELF in `assets/`
+    elf: &'static str,
+    /// Prove the program on a task's public inputs
+    run: RunFn,
+}
+
+/// Provenance of the bundled ELFs, written by `scripts/build-guests.sh`
+const MANIFEST: &str = include_str!("../assets/manifest.json");
+
+#[derive(Debug, Deserialize)]
+struct Manifest {
+    programs: Vec<BundledElf>,
+}
+
+#[derive(Debug, Deserialize)]
+struct BundledElf {
+    name: String,
+    /// SHA-256 of the ELF
+    sha256: String,
+    /// Path of the program's source, relative to the crate
+    #[cfg_attr(not(test), allow(dead_code))]
+    source: String,
+    /// SHA-256 of the source the ELF was built from
+    #[cfg_attr(not(test), allow(dead_code))]
+    source_sha256: String,
+}
+
+fn manifest() -> Manifest {
+    serde_json::from_str(MANIFEST).expect("assets/manifest.json is invalid")
+}
+
+fn sha256(bytes: &[u8]) -> String {
+    hex::encode(Sha256::digest(bytes))
+}
+
+/// Every program the node can prove. The first is proven for tasks that name none.
+pub const PROGRAMS: &[Program] = &[
+    Program {
+        id: "fib_input",
+        elf: "fib_input",
+        run: run_fib_input,
+    },
+    Program {
+        id: "keccak_input",
+        elf: "keccak_input",
+        run: run_keccak_input,
+    },
+];
+
+pub fn find(id: &str) -> Option<&'static Program> {
+    PROGRAMS.iter().find(|program| program.id == id)
+}
+
+/// The program to prove for a task.
+///
+/// Orchestrators that predate the registry do not name a program, and expect the
+/// first one. A program this node does not know is an error: proving another one
+/// instead would only produce a proof the orchestrator rejects.
+pub fn for_task(program_id: &str) -> Result<&'static Program, String> {
+    if program_id.is_empty() {
+        return Ok(&PROGRAMS[0]);
+    }
+    find(program_id).ok_or_else(|| format!("unknown program '{}'", program_id))
+}
+
+/// The registered program whose bundled ELF is `elf`, for requests that carry the ELF
+/// instead of a program ID
+pub fn for_elf(elf: &[u8]) -> Option<&'static Program> {
+    let hash = sha256(elf);
+    let bundled = manifest()
+        .programs
+        .into_iter()
+        .find(|bundled| bundled.sha256 == hash)?;
+    PROGRAMS.iter().find(|program| program.elf == bundled.name)
+}
+
+impl Program {
+    pub fn elf_path(&self) -> PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("assets")
+            .join(self.elf)
+    }
+
+    /// Prove the program on `public_inputs`, until cancelled
+    pub fn prove(
+        &self,
+        public_inputs: &[u8],
+        cancelled: &CancellationToken,
+    ) -> Result<GuestRun, String> {
+        self.job(public_inputs, false).run_in_child(cancelled)
+    }
+
+    /// [`Program::prove`], also counting the guest's cycles
+    pub fn prove_counting_cycles(
+        &self,
+        public_inputs: &[u8],
+        cancelled: &CancellationToken,
+    ) -> Result<GuestRun, String> {
+        self.job(public_inputs, true).run_in_child(cancelled)
+    }
+
+    fn job(&self, public_inputs: &[u8], count_cycles: bool) -> Job {
+        Job::Program {
+            id: self.id.to_string(),
+            public_inputs: public_inputs.to_vec(),
+            count_cycles,
+        }
+    }
+
+    /// Load the program and prove it in this process
+    fn prove_here(&self, public_inputs: &[u8], count_cycles: bool) -> Result<GuestRun, String> {
+        let elf = self.verified_elf()?;
+        let prover = load(&elf)?;
+        let traced = count_cycles.then_some(elf.as_slice());
+        (self.run)(prover, traced, public_inputs)
+    }
+
+    /// The program's ELF, if it is the one recorded in the manifest
+    fn verified_elf(&self) -> Result<Vec<u8>, String> {
+        let path = self.elf_path();
+        let elf = std::fs::read(&path)
+            .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+        let expected = manifest()
+            .programs
+            .into_iter()
+            .find(|bundled| bundled.name == self.elf)
+            .map(|bundled| bundled.sha256);
+        if expected.as_deref() != Some(sha256(&elf).as_str()) {
+            return Err(format!(
+                "{} does not match assets/manifest.json",
+                path.display()
+            ));
+        }
+        Ok(elf)
+    }
+}
+
+/// Prove a guest program that is not registered, from its ELF, and count its cycles,
+/// until cancelled.
+///
+/// The node cannot know the type of such a program's public output, so it is not decoded.
+pub fn prove_elf(
+    path: &Path,
+    private_input: &Input,
+    public_input: &Input,
+    cancelled: &CancellationToken,
+) -> Result<GuestRun, String> {
+    let job = Job::Elf {
+        path: path.to_path_buf(),
+        private_input: private_input.to_arg(),
+        public_input: public_input.to_arg(),
+    };
+    job.run_in_child(cancelled)
+}
+
+fn prove_elf_here(
+    path: &Path,
+    private_input: &Input,
+    public_input: &Input,
+) -> Result<GuestRun, String> {
+    let elf =
+        std::fs::read(path).map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+    let prover = load(&elf)?;
+    guest::prove_with_private_input::<Input, Input, ()>(
+        prover,
+        Some(&elf),
+        private_input,
+        public_input,
+    )
+}
+
+fn load(elf: &[u8]) -> Result<Stwo<Local>, String> {
+    info_span!("load_elf")
+        .in_scope(|| Stwo::<Local>::new_from_bytes(elf))
+        .map_err(|e| format!("Failed to load guest program: {}", e))
+}
+
+/// A proof for a child process to run
+#[derive(Debug, Serialize, Deserialize)]
+pub enum Job {
+    /// A registered program, by ID
+    Program {
+        id: String,
+        public_inputs: Vec<u8>,
+        count_cycles: bool,
+    },
+    /// An ELF that is not registered, with its inputs as given on the command line
+    Elf {
+        path: PathBuf,
+        private_input: Option<String>,
+        public_input: Option<String>,
+    },
+}
+
+impl Job {
+    /// Run the job in a child process, killing it if cancelled
+    fn run_in_child(&self, cancelled: &CancellationToken) -> Result<GuestRun, String> {
+        let request = serde_json::to_vec(self).map_err(|e| format!("Invalid job: {}", e))?;
+        let response = info_span!("prove").in_scope(|| proving::run_child(&request, cancelled))?;
+        bincode::deserialize::<Result<GuestRun, String>>(&response)
+            .map_err(|e| format!("Invalid response from the prover: {}", e))?
+    }
+
+    fn run(&self) -> Result<GuestRun, String> {
+        match self {
+            Job::Program {
+                id,
+                public_inputs,
+                count_cycles,
+            } => {
+                let program = find(id).ok_or_else(|| format!("unknown program '{}'", id))?;
+                program.prove_here(public_inputs, *count_cycles)
+            }
+            Job::Elf {
+                path,
+                private_input,
+                public_input,
+            } => {
+                let parse = |input: &Option<String>| -> Result<Input, String> {
+                    Ok(input
+                        .as_deref()
+                        .map(str::parse)
+                        .transpose()?
+                        .unwrap_or_default())
+                };
+                prove_elf_here(path, &parse(private_input)?, &parse(public_input)?)
+            }
+        }
+    }
+}
+
+/// Run the job the node hands this process on stdin, and write its result to stdout,
+/// for `prove-job`
+pub fn serve_job() -> Result<(), Box<dyn std::error::Error>> {
+    let job: Job = serde_json::from_reader(std::io::stdin())?;
+    let result = job.run();
+    let mut stdout = std::io::stdout().lock();
+    stdout.write_all(&bincode::serialize(&result)?)?;
+    stdout.flush()?;
+    Ok(())
+}
+
+/// Computes the Fibonacci number of the first input byte, and only prints it
+fn run_fib_input(
+    prover: Stwo<Local>,
+    traced: Option<&[u8]>,
+    public_inputs: &[u8],
+) -> Result<GuestRun, String> {
+    let n = public_inputs.first().copied().unwrap_or_default() as u32;
+    guest::prove::<u32, ()>(prover, traced, &n)
+}
+
+/// Hashes the public inputs with Keccak-256, and commits the 32-byte digest.
+///
+/// The inputs are handed to the guest as a length-prefixed byte string (`Vec<u8>`).
+/// Hashing workloads rely on the digest, so it is checked against the host's own
+/// Keccak-256 before the proof can be submitted.
+fn run_keccak_input(
+    prover: Stwo<Local>,
+    traced: Option<&[u8]>,
+    public_inputs: &[u8],
+) -> Result<GuestRun, String> {
+    let run = guest::prove::<Vec<u8>, [u8; 32]>(prover, traced, &public_inputs.to_vec())?;
+    if run.outcome.is_success() {
+        check_keccak_digest(public_inputs, run.public_output.as_ref())?;
+    }
+    Ok(run)
+}
+
+fn check_keccak_digest(
+    public_inputs: &[u8],
+    digest: Option<&serde_json::Value>,
+) -> Result<(), String> {
+    let expected: [u8; 32] = Keccak256::digest(public_inputs).into();
+    let digest: Option<[u8; 32]> =
+        digest.and_then(|digest| serde_json::from_value(digest.clone()).ok());
+    match digest {
+        Some(digest) if digest == expected => Ok(()),
+        Some(digest) => Err(format!(
+            "Guest digest {} is not the Keccak-256 of its input, {}",
+            hex::encode(digest),
+            hex::encode(expected)
+        )),
+        None => Err("Guest did not commit a digest".to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_every_program_is_bundled_under_a_unique_id() {
+        for (i, program) in PROGRAMS.iter().enumerate() {
+            assert!(
+                program.elf_path().is_file(),
+                "{} has no ELF; run `scripts/build-guests.sh`",
+                program.id
+            );
+            assert_eq!(find(program.id).map(|p| p.id), Some(program.id));
+            assert!(PROGRAMS[..i].iter().all(|other| other.id != program.id));
+        }
+    }
+
+    #[test]
+    fn test_bundled_elfs_match_their_sources() {
+        let crate_dir = Path::new(env!("CARGO_MANIFEST_DIR"));
+        let manifest = manifest();
+        for program in PROGRAMS {
+            let bundled = manifest
+                .programs
+                .iter()
+                .find(|bundled| bundled.name == program.elf)
+                .unwrap_or_else(|| {
+                    panic!(
+                        "{} is not in assets/manifest.json; run `scripts/build-guests.sh`",
+                        program.id
+                    )
+                });
+            assert!(
+                program.verified_elf().is_ok(),
+                "{} was modified",
+                program.id
+            );
+
+            let source = std::fs::read(crate_dir.join(&bundled.source)).unwrap();
+            assert_eq!(
+                sha256(&source),
+                bundled.source_sha256,
+                "{} changed since its ELF was built; run `scripts/build-guests.sh`",
+                bundled.source
+            );
+        }
+    }
+
+    #[test]
+    #[ignore = "builds the examples, which needs the riscv32i-unknown-none-elf target"]
+    fn test_bundled_elfs_are_reproducible() {
+        let crate_dir = Path::new(env!("CARGO_MANIFEST_DIR"));
+        let out_dir = std::env::temp_dir().join(format!("nexus-guests-{}", std::process::id()));
+        let status = std::process::Command::new(crate_dir.join("scripts/build-guests.sh"))
+            .arg(&out_dir)
+            .status()
+            .unwrap();
+        assert!(status.success());
+
+        // Down to the toolchain it claims to have been built with
+        let rebuilt: serde_json::Value =
+            serde_json::from_slice(&std::fs::read(out_dir.join("manifest.json")).unwrap()).unwrap();
+        assert_eq!(
+            rebuilt,
+            serde_json::from_str::<serde_json::Value>(MANIFEST).unwrap()
+        );
+        for program in PROGRAMS {
+            assert_eq!(
+                std::fs::read(out_dir.join(program.elf)).unwrap(),
+                std::fs::read(program.elf_path()).unwrap(),
+                "{} does not rebuild to the bundled ELF",
+                program.id
+            );
+        }
+        std::fs::remove_dir_all(&out_dir).unwrap();
+    }
+
+    #[test]
+    fn test_keccak_digest_is_checked_against_sha3() {
+        // Keccak-256 of the empty string
+        let empty = "c5d2460186f7233c927e7db2dcc703c0e500b653ca82273b7bfad8045d85a470";
+        let digest = serde_json::json!(hex::decode(empty).unwrap());
+        assert_eq!(check_keccak_digest(b"", Some(&digest)), Ok(()));
+
+        let error = check_keccak_digest(b"abc", Some(&digest)).unwrap_err();
+        assert!(error.contains(empty), "{}", error);
+        assert!(check_keccak_digest(b"", None).is_err());
+    }
+
+    #[test]
+    fn test_requests_are_matched_to_programs_by_their_elf() {
+        let elf = std::fs::read(PROGRAMS[0].elf_path()).unwrap();
+        assert_eq!(for_elf(&elf).map(|program| program.id), Some("fib_input"));
+        assert!(for_elf(b"not a bundled program").is_none());
+    }
+
+    #[test]
+    fn test_only_tasks_without_a_program_get_the_default() {
+        assert_eq!(for_task("").unwrap().id, "fib_input");
+        assert_eq!(for_task("keccak_input").unwrap().id, "keccak_input");
+        assert!(for_task("no-such-program")
+            .unwrap_err()
+            .contains("no-such-program"));
+    }
+}
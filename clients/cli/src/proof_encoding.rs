@@ -0,0 +1,228 @@
+//! Wire encoding of proofs submitted to the orchestrator.
+//!
+//! A proof is first serialized, as JSON or as compact `bincode`, and the serialized
+//! bytes are then optionally compressed with zstd or gzip. When fetching a task the
+//! node lists what it supports, and the orchestrator answers with the encoding it
+//! wants for that task's proof. An orchestrator that does not know about encodings
+//! answers with none, and the proof is sent as uncompressed JSON as before.
+//!
+//! The proof hash is always the Keccak-256 of the serialized bytes before
+//! compression, so the orchestrator can check it after decompressing.
+
+use crate::nexus_orchestrator::{ProofCompression, ProofEncoding, ProofSerialization};
+use flate2::write::GzEncoder;
+use serde::Serialize;
+use sha3::{Digest, Keccak256};
+use std::io::Write;
+
+/// Serializations this node can produce, advertised when fetching a task
+pub const SUPPORTED_SERIALIZATIONS: [ProofSerialization; 2] =
+    [ProofSerialization::Json, ProofSerialization::Bincode];
+
+/// Compressions this node can produce, advertised when fetching a task
+pub const SUPPORTED_COMPRESSIONS: [ProofCompression; 3] = [
+    ProofCompression::None,
+    ProofCompression::Zstd,
+    ProofCompression::Gzip,
+];
+
+/// zstd level used for proofs; higher levels cost much more CPU for little gain
+const ZSTD_LEVEL: i32 = 3;
+
+/// A proof ready to be uploaded
+#[derive(Debug, Clone)]
+pub struct EncodedProof {
+    /// The bytes to upload
+    pub bytes: Vec<u8>,
+    /// Hex-encoded Keccak-256 of the serialized proof, before compression
+    pub hash: String,
+    /// Size of the serialized proof, before compression
+    pub serialized_len: usize,
+    pub encoding: ProofEncoding,
+}
+
+impl EncodedProof {
+    /// Stands in for the proof of a guest that never finished
+    pub fn empty(encoding: ProofEncoding) -> Self {
+        Self {
+            bytes: Vec::new(),
+            hash: String::new(),
+            serialized_len: 0,
+            encoding,
+        }
+    }
+
+    /// Short description of the encoding and sizes, for logs
+    pub fn describe(&self) -> String {
+        let encoding = describe(&self.encoding);
+        if self.encoding.compression() == ProofCompression::None {
+            format!("{} bytes, {}", self.bytes.len(), encoding)
+        } else {
+            format!(
+                "{} bytes ({} before compression), {}",
+                self.bytes.len(),
+                self.serialized_len,
+                encoding
+            )
+        }
+    }
+}
+
+/// Name of an encoding, e.g. `bincode+zstd`
+pub fn describe(encoding: &ProofEncoding) -> String {
+    let serialization = match encoding.serialization() {
+        ProofSerialization::Json => "json",
+        ProofSerialization::Bincode => "bincode",
+    };
+    match encoding.compression() {
+        ProofCompression::None => serialization.to_string(),
+        ProofCompression::Zstd => format!("{}+zstd", serialization),
+        ProofCompression::Gzip => format!("{}+gzip", serialization),
+    }
+}
+
+/// The encoding to use for a task, given what the orchestrator asked for.
+///
+/// Values this node does not know are replaced by the defaults (JSON, uncompressed),
+/// and the submission says so.
+pub fn negotiate(requested: Option<ProofEncoding>) -> ProofEncoding {
+    let requested = requested.unwrap_or_default();
+    // The generated getters fall back to the default for unknown values
+    ProofEncoding {
+        serialization: requested.serialization() as i32,
+        compression: requested.compression() as i32,
+    }
+}
+
+/// Serialize, hash and compress a proof
+pub fn encode<P: Serialize>(
+    proof: &P,
+    encoding: ProofEncoding,
+) -> Result<EncodedProof, Box<dyn std::error::Error>> {
+    let serialized = match encoding.serialization() {
+        ProofSerialization::Json => serde_json::to_vec(proof)?,
+        ProofSerialization::Bincode => bincode::serialize(proof)?,
+    };
+    let hash = format!("{:x}", Keccak256::digest(&serialized));
+    let serialized_len = serialized.len();
+
+    let bytes = match encoding.compression() {
+        ProofCompression::None => serialized,
+        ProofCompression::Zstd => zstd::encode_all(serialized.as_slice(), ZSTD_LEVEL)?,
+        ProofCompression::Gzip => {
+            let mut encoder = GzEncoder::new(Vec::new(), flate2::Compression::default());
+            encoder.write_all(&serialized)?;
+            encoder.finish()?
+        }
+    };
+
+    Ok(EncodedProof {
+        bytes,
+        hash,
+        serialized_len,
+        encoding,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde::de::DeserializeOwned;
+    use serde::Deserialize;
+    use std::io::Read;
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct FakeProof {
+        commitments: Vec<[u8; 32]>,
+        steps: u64,
+    }
+
+    fn fake_proof() -> FakeProof {
+        FakeProof {
+            commitments: vec![[7u8; 32]; 64],
+            steps: 1 << 20,
+        }
+    }
+
+    /// What the orchestrator does with a submission
+    fn decode<P: DeserializeOwned>(proof: &EncodedProof) -> (P, String) {
+        let serialized = match proof.encoding.compression() {
+            ProofCompression::None => proof.bytes.clone(),
+            ProofCompression::Zstd => zstd::decode_all(proof.bytes.as_slice()).unwrap(),
+            ProofCompression::Gzip => {
+                let mut serialized = Vec::new();
+                flate2::read::GzDecoder::new(proof.bytes.as_slice())
+                    .read_to_end(&mut serialized)
+                    .unwrap();
+                serialized
+            }
+        };
+        let hash = format!("{:x}", Keccak256::digest(&serialized));
+        let decoded = match proof.encoding.serialization() {
+            ProofSerialization::Json => serde_json::from_slice(&serialized).unwrap(),
+            ProofSerialization::Bincode => bincode::deserialize(&serialized).unwrap(),
+        };
+        (decoded, hash)
+    }
+
+    #[test]
+    fn test_every_encoding_round_trips_with_hash_of_uncompressed_bytes() {
+        for serialization in SUPPORTED_SERIALIZATIONS {
+            let uncompressed = encode(
+                &fake_proof(),
+                ProofEncoding {
+                    serialization: serialization as i32,
+                    compression: ProofCompression::None as i32,
+                },
+            )
+            .unwrap();
+
+            for compression in SUPPORTED_COMPRESSIONS {
+                let encoding = ProofEncoding {
+                    serialization: serialization as i32,
+                    compression: compression as i32,
+                };
+                let encoded = encode(&fake_proof(), encoding).unwrap();
+                let (decoded, hash): (FakeProof, _) = decode(&encoded);
+
+                assert_eq!(decoded, fake_proof(), "{}", describe(&encoding));
+                assert_eq!(hash, encoded.hash);
+                // Compression never changes the hash
+                assert_eq!(encoded.hash, uncompressed.hash);
+            }
+        }
+    }
+
+    #[test]
+    fn test_binary_and_compressed_encodings_are_smaller_than_json() {
+        let encode_as = |serialization: ProofSerialization, compression: ProofCompression| {
+            let encoding = ProofEncoding {
+                serialization: serialization as i32,
+                compression: compression as i32,
+            };
+            encode(&fake_proof(), encoding).unwrap().bytes.len()
+        };
+
+        let json = encode_as(ProofSerialization::Json, ProofCompression::None);
+        let bincode = encode_as(ProofSerialization::Bincode, ProofCompression::None);
+        let compressed = encode_as(ProofSerialization::Bincode, ProofCompression::Zstd);
+        assert!(bincode < json);
+        assert!(compressed < bincode);
+    }
+
+    #[test]
+    fn test_unknown_or_missing_encoding_falls_back_to_json() {
+        let fallback = ProofEncoding {
+            serialization: ProofSerialization::Json as i32,
+            compression: ProofCompression::None as i32,
+        };
+        assert_eq!(negotiate(None), fallback);
+        assert_eq!(
+            negotiate(Some(ProofEncoding {
+                serialization: 42,
+                compression: 42,
+            })),
+            fallback
+        );
+    }
+}
@@ -1,137 +1,414 @@
-use nexus_sdk::{stwo::seq::Stwo, Local, Prover, Viewable};
-
 use crate::analytics;
+use crate::archive;
 use crate::config;
+use crate::connection;
 use crate::flops;
+use crate::guest;
+use crate::lease::{self, Lease, ProvingRate};
+use crate::memory_stats;
+use crate::network::NetworkSettings;
+use crate::nexus_orchestrator::{
+    compiled_program, proof, vm_program_input, LeaseReleaseReason, Proof, ProofEncoding,
+    ProofRequest,
+};
 use crate::orchestrator_client::OrchestratorClient;
+use crate::programs;
+use crate::proof_encoding::{self, EncodedProof};
+use crate::proving::{ProverPool, ProvingSettings};
 use crate::setup;
 use crate::utils;
 use colored::Colorize;
-use sha3::{Digest, Keccak256};
 use log::{error, warn};
+use serde::Serialize;
+use std::sync::Arc;
 use std::time::Duration;
+use tokio_util::sync::CancellationToken;
+use tracing::info_span;
+
+/// What became of one proof, printed as JSON by `prove`
+#[derive(Debug, Serialize)]
+pub struct ProofSummary {
+    /// `None` for proofs of local inputs
+    pub task_id: Option<String>,
+    pub program_id: String,
+    /// How the guest program ended
+    pub outcome: String,
+    /// Size of the proof as uploaded
+    pub proof_size: usize,
+    pub proof_hash: String,
+    pub duration_ms: u64,
+    /// Cycles the guest ran for in the zkVM, if they were counted
+    pub cycles: Option<u64>,
+    /// Memory used by the node once the proof was done, in MB
+    pub memory_mb: f32,
+    /// The guest's public output, decoded by its program
+    pub public_output: Option<serde_json::Value>,
+}
+
+impl ProofSummary {
+    pub fn new(
+        task_id: Option<String>,
+        program_id: String,
+        run: &guest::GuestRun,
+        proof: &EncodedProof,
+        duration: Duration,
+    ) -> Self {
+        let (memory, _) = memory_stats::get_memory_info();
+        Self {
+            task_id,
+            program_id,
+            outcome: run.outcome.to_string(),
+            proof_size: proof.bytes.len(),
+            proof_hash: proof.hash.clone(),
+            duration_ms: duration.as_millis() as u64,
+            cycles: run.cycles,
+            memory_mb: memory_stats::mb_i32_to_f32(memory),
+            public_output: run.public_output.clone(),
+        }
+    }
+}
 
 /// Proves a program with a given node ID
-#[allow(dead_code)]
+#[tracing::instrument(
+    name = "proof_task",
+    skip(client, pool, settings),
+    fields(task_id = tracing::field::Empty)
+)]
 async fn authenticated_proving(
     node_id: &str,
-    environment: &config::Environment,
-) -> Result<(), Box<dyn std::error::Error>> {
-    let client = OrchestratorClient::new(environment.clone());
-
+    client: &OrchestratorClient,
+    pool: &ProverPool,
+    settings: &ProvingSettings,
+) -> Result<ProofSummary, Box<dyn std::error::Error>> {
+    // A lease starts running when the task is handed out, not when a worker is free
+    pool.wait_for_worker().await;
     println!("Fetching a task to prove from Nexus Orchestrator...");
     let proof_task = match client.get_proof_task(node_id).await {
         Ok(task) => {
             println!("Successfully fetched task from Nexus Orchestrator.");
             task
-        },
+        }
         Err(_) => {
             println!("Using local inputs.");
-            return anonymous_proving();
-        },
+            return pooled_anonymous_proving(pool).await;
+        }
     };
+    tracing::Span::current().record("task_id", proof_task.task_id.as_str());
 
-    let public_input: u32 = proof_task.public_inputs.first().cloned().unwrap_or_default() as u32;
-
-    println!("Compiling guest program...");
-    let elf_file_path = std::path::Path::new(env!("CARGO_MANIFEST_DIR"))
-        .join("assets")
-        .join("fib_input");
-    let prover =
-        match Stwo::<Local>::new_from_file(&elf_file_path){
-            Ok(prover) => prover,
-            Err(e) => {
-                error!("Failed to load guest program: {}", e);
-                return Err(e.into())
-            }
-        };
+    let mut lease = Lease::new(&proof_task.task_id, proof_task.lease_expires_at_ms);
+    let program = match programs::for_task(&proof_task.program_id) {
+        Ok(program) => program,
+        Err(e) => {
+            println!("Skipping task {}: {}", proof_task.task_id, e);
+            release_lease(
+                client,
+                node_id,
+                &lease,
+                LeaseReleaseReason::UnsupportedProgram,
+            )
+            .await;
+            return Err(format!("Skipped task {}", proof_task.task_id).into());
+        }
+    };
 
-    println!("Creating ZK proof with inputs...");
-    let (view, proof) = match prover
-        .prove_with_input::<(), u32>(&(), &public_input){
-            Ok(result) => result,
-            Err(e) => {
-                error!("Failed to run prover: {}", e);
-                return Err(e.into());
-            }
-        };
+    let rate = ProvingRate::load();
+    if let Some(reason) = lease::reason_to_skip(&lease, proof_task.estimated_cycles, rate) {
+        println!("Skipping task {}: {}", proof_task.task_id, reason);
+        release_lease(
+            client,
+            node_id,
+            &lease,
+            LeaseReleaseReason::InsufficientTime,
+        )
+        .await;
+        return Err(format!("Skipped task {}", proof_task.task_id).into());
+    }
 
-    let code = view.exit_code()
-        .map(|u| u as i32) // convert on success
-        .unwrap_or_else(|_err| {
-            eprintln!("Failed to retrieve exit code: {:?}", _err);
-            -1
-        });
-    
-    assert_eq!(code, 0, "Unexpected exit code!");
-
-    let proof_bytes = match serde_json::to_vec(&proof) {
-        Ok(bytes) => bytes,
-        Err(e) => {
-            error!("Failed to serialize proof: {}", e);
+    println!("Compiling guest program {}...", program.id);
+    let public_inputs = proof_task.public_inputs.clone();
+    let span = tracing::Span::current();
+    let proving = pool.run(move |cancelled: &CancellationToken| {
+        println!("Creating ZK proof with inputs...");
+        span.in_scope(|| program.prove(&public_inputs, cancelled))
+    });
+    let started = std::time::Instant::now();
+    let run = match lease::hold_while(proving, &mut lease, client, node_id).await {
+        Some(Ok(Ok(result))) => result,
+        Some(Ok(Err(e))) => {
+            error!("{}", e);
+            release_lease(client, node_id, &lease, LeaseReleaseReason::ProvingFailed).await;
             return Err(e.into());
         }
+        Some(Err(stopped)) => {
+            // Timed out, aborted by the user, or shutting down
+            error!("{}", stopped);
+            release_lease(client, node_id, &lease, LeaseReleaseReason::ProvingFailed).await;
+            return Err(stopped.into());
+        }
+        None => {
+            // Dropping the proving future has cancelled the job
+            error!(
+                "Lease on task {} expired before the proof was ready; abandoning it",
+                lease.task_id
+            );
+            return Err(format!("Lease on task {} expired", lease.task_id).into());
+        }
     };
-    let proof_hash = format!("{:x}", Keccak256::digest(&proof_bytes));
+    if let Some(cycles) = proof_task.estimated_cycles {
+        if let Some(rate) = ProvingRate::record(rate, cycles, started.elapsed()) {
+            rate.save();
+        }
+    }
+
+    // A failing guest is the task's problem, not the node's: report it and move on
+    if !run.outcome.is_success() {
+        warn!(
+            "Guest program for task {} {}; reporting it to the orchestrator",
+            proof_task.task_id, run.outcome
+        );
+    }
+
+    let encoding = proof_encoding::negotiate(proof_task.proof_encoding);
+    let encoded_proof = match &run.proof {
+        Some(proof) => info_span!("serialize_proof")
+            .in_scope(|| proof_encoding::encode(proof, encoding))
+            .map_err(|e| {
+                error!("Failed to serialize proof: {}", e);
+                e
+            })?,
+        None => EncodedProof::empty(encoding),
+    };
+
+    if lease.is_expired() {
+        error!(
+            "Lease on task {} expired while serializing the proof; not submitting it",
+            lease.task_id
+        );
+        return Err(format!("Lease on task {} expired", lease.task_id).into());
+    }
+
+    if let Some(dir) = &settings.archive_dir {
+        match archive::save(dir, &proof_task.task_id, &encoded_proof, &run) {
+            Ok(path) => println!("Archived proof as {}.proof", path.display()),
+            Err(e) => warn!("Failed to archive proof in {}: {}", dir.display(), e),
+        }
+    }
 
-    println!("Submitting ZK proof to Nexus Orchestrator...");
+    println!(
+        "Submitting ZK proof to Nexus Orchestrator ({})...",
+        encoded_proof.describe()
+    );
+    let summary = ProofSummary::new(
+        Some(proof_task.task_id.clone()),
+        program.id.to_string(),
+        &run,
+        &encoded_proof,
+        started.elapsed(),
+    );
+    let report = run.report(settings.attach_guest_output);
     if let Err(e) = client
-        .submit_proof(&proof_task.task_id, &proof_hash, proof_bytes)
-        .await{
-            error!("Failed to submit proof: {}", e);
-            return Err(e);
+        .submit_proof(&proof_task.task_id, encoded_proof, report)
+        .await
+    {
+        error!("Failed to submit proof: {}", e);
+        return Err(e);
+    }
+
+    if run.outcome.is_success() {
+        println!("{}", "ZK proof successfully submitted".green());
+    } else {
+        let message = format!("Reported that the guest program {}", run.outcome);
+        println!("{}", message.yellow());
+    }
+    Ok(summary)
+}
+
+/// Give a task back to the orchestrator; failing to do so only costs it the lease time
+async fn release_lease(
+    client: &OrchestratorClient,
+    node_id: &str,
+    lease: &Lease,
+    reason: LeaseReleaseReason,
+) {
+    if let Err(e) = client.release_lease(node_id, &lease.task_id, reason).await {
+        warn!("Failed to release task {}: {}", lease.task_id, e);
+    }
+}
+
+/// Measure the proving rate if no earlier run has, so a fresh node can already skip
+/// tasks it cannot prove before their lease ends
+async fn calibrate_proving_rate(pool: &ProverPool) {
+    if ProvingRate::load().is_some() {
+        return;
+    }
+    println!("Measuring the proving rate of this node...");
+    match pool.run(ProvingRate::calibrate).await {
+        Ok(Ok(rate)) => {
+            println!("Proving rate: {:.0} cycles/s", rate.cycles_per_sec);
+            rate.save();
         }
-    
-    println!("{}", "ZK proof successfully submitted".green());
-    Ok(())
+        Ok(Err(e)) => warn!("Failed to measure the proving rate: {}", e),
+        Err(stopped) => warn!("Failed to measure the proving rate: {}", stopped),
+    }
+}
+
+/// Run [`anonymous_proving`] on the prover pool
+async fn pooled_anonymous_proving(
+    pool: &ProverPool,
+) -> Result<ProofSummary, Box<dyn std::error::Error>> {
+    let proving = pool.run(|cancelled| anonymous_proving(cancelled).map_err(|e| e.to_string()));
+    match proving.await {
+        Ok(result) => Ok(result?),
+        Err(stopped) => Err(stopped.into()),
+    }
 }
 
-fn anonymous_proving() -> Result<(), Box<dyn std::error::Error>> {
+fn anonymous_proving(
+    cancelled: &CancellationToken,
+) -> Result<ProofSummary, Box<dyn std::error::Error>> {
     // 1. Instead of fetching the proof task from the orchestrator, we will use hardcoded input program and values
 
     // The 10th term of the Fibonacci sequence is 55
-    let public_input: u32 = 9;
+    let public_inputs = [9u8];
+    let program = &programs::PROGRAMS[0];
     println!("Compiling guest program...");
-    let elf_file_path = std::path::Path::new(env!("CARGO_MANIFEST_DIR"))
-        .join("assets")
-        .join("fib_input");
-
-    let prover = Stwo::<Local>::new_from_file(&elf_file_path)
-        .map_err(|e| {
-            error!("Failed to load guest program: {}", e);
-            e
-        })?;
 
     //3. Run the prover
     println!("Creating ZK proof (anonymous)...");
-    let (view, proof) = prover
-        .prove_with_input::<(), u32>(&(), &public_input)
-        .map_err(|e| {
-            error!("Failed to run prover: {}", e);
-            e
+    let started = std::time::Instant::now();
+    let run = program.prove(&public_inputs, cancelled).map_err(|e| {
+        error!("{}", e);
+        e
     })?;
+    let proof = match &run.proof {
+        Some(proof) if run.outcome.is_success() => proof,
+        _ => {
+            error!("Guest program {}", run.outcome);
+            return Err(format!("Guest program {}", run.outcome).into());
+        }
+    };
 
-    let exit_code = view.exit_code().expect("Failed to retrieve exit code");
-    if exit_code != 0 {
-        error!("Unexpected exit code: {} (expected 0)", exit_code);
-        return Err(format!("Exit code was {}", exit_code).into());
-    }
-
-    let proof_bytes = serde_json::to_vec(&proof)?;
+    // Serialized as it would be submitted to an orchestrator that asks for no encoding
+    let encoded_proof = proof_encoding::encode(proof, ProofEncoding::default())?;
     println!(
         "{}",
         format!(
             "ZK proof created (anonymous) with size: {} bytes",
-            proof_bytes.len()
+            encoded_proof.bytes.len()
         )
         .green()
     );
+    Ok(ProofSummary::new(
+        None,
+        program.id.to_string(),
+        &run,
+        &encoded_proof,
+        started.elapsed(),
+    ))
+}
+
+/// Proves a request pushed by the orchestrator over a streaming session.
+///
+/// Requests carry their program's ELF. Only ELFs bundled with this node are proven, as
+/// their registered programs, so inputs and outputs are handled as for polled tasks.
+fn streamed_proving(
+    request: ProofRequest,
+    attach_guest_output: bool,
+    cancelled: &CancellationToken,
+) -> Result<Proof, String> {
+    let Some(compiled_program::Program::Rv32iElfBytes(elf)) =
+        request.program.and_then(|program| program.program)
+    else {
+        return Err("Request does not contain a program".to_string());
+    };
+    let program = programs::for_elf(&elf)
+        .ok_or("Request is for a program this node does not bundle".to_string())?;
+    let public_inputs = match request.input.and_then(|input| input.input) {
+        Some(vm_program_input::Input::RawBytes(bytes)) => bytes,
+        None => Vec::new(),
+    };
+
+    println!(
+        "Creating ZK proof of {} for streamed request...",
+        program.id
+    );
+    let run = program.prove(&public_inputs, cancelled)?;
+    if !run.outcome.is_success() {
+        warn!(
+            "Guest program {}; reporting it to the orchestrator",
+            run.outcome
+        );
+    }
+
+    let encoding = proof_encoding::negotiate(request.proof_encoding);
+    let encoded_proof = match &run.proof {
+        Some(proof) => info_span!("serialize_proof")
+            .in_scope(|| proof_encoding::encode(proof, encoding))
+            .map_err(|e| format!("Failed to serialize proof: {}", e))?,
+        None => EncodedProof::empty(encoding),
+    };
+    let report = run.report(attach_guest_output);
+    Ok(Proof {
+        proof: run
+            .proof
+            .is_some()
+            .then_some(proof::Proof::StwoBytes(encoded_proof.bytes)),
+        proof_encoding: Some(encoded_proof.encoding),
+        proof_hash: encoded_proof.hash,
+        guest_outcome: report.guest_outcome,
+        guest_output: report.guest_output,
+        public_output: report.public_output,
+    })
+}
+
+/// Prove the bundled program once, so a freshly installed binary can show it works
+pub fn self_check() -> Result<(), Box<dyn std::error::Error>> {
+    anonymous_proving(&CancellationToken::new())?;
     Ok(())
 }
 
+/// Prove tasks without the interactive setup, printing a JSON summary of each.
+///
+/// Proves for `node_id` if there is one, anonymously otherwise. With `once`, stops after
+/// the first proof and returns its error, if any.
+pub async fn prove_tasks(
+    environment: &config::Environment,
+    node_id: Option<&str>,
+    network: &NetworkSettings,
+    tls_config: Arc<rustls::ClientConfig>,
+    pool: &ProverPool,
+    settings: &ProvingSettings,
+    once: bool,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let client = OrchestratorClient::new(environment.clone(), network, &tls_config)?;
+    if node_id.is_some() {
+        calibrate_proving_rate(pool).await;
+    }
+    loop {
+        let result = match node_id {
+            Some(node_id) => authenticated_proving(node_id, &client, pool, settings).await,
+            None => pooled_anonymous_proving(pool).await,
+        };
+        match result {
+            Ok(summary) => println!("{}", serde_json::to_string_pretty(&summary)?),
+            Err(e) if once => return Err(e),
+            Err(e) => warn!("Proof failed: {}", e),
+        }
+        if once {
+            return Ok(());
+        }
+        tokio::time::sleep(Duration::from_secs(2)).await;
+    }
+}
+
 /// Starts the prover, which can be anonymous or connected to the Nexus Orchestrator
 pub async fn start_prover(
     environment: &config::Environment,
+    protocol: connection::Protocol,
+    network: &NetworkSettings,
+    tls_config: Arc<rustls::ClientConfig>,
+    pool: Arc<ProverPool>,
+    settings: &ProvingSettings,
 ) -> Result<(), Box<dyn std::error::Error>> {
     // Print the banner at startup
     utils::cli_branding::print_banner();
@@ -172,9 +449,10 @@ pub async fn start_prover(
 
                 while attempt <= max_attempts {
                     println!("Attempt #{} for anonymous proving", attempt);
-                    match anonymous_proving() {
+                    match pooled_anonymous_proving(&pool).await {
                         Ok(_) => {
                             println!("Anonymous proving succeeded on attempt #{attempt}!");
+                            utils::updater::confirm_update_health();
                             success = true;
                             break;
                         }
@@ -243,6 +521,41 @@ pub async fn start_prover(
             );
 
             let client_id = format!("{:x}", md5::compute(node_id.as_bytes()));
+            calibrate_proving_rate(&pool).await;
+
+            if protocol == connection::Protocol::Streaming {
+                let proof_count = std::sync::atomic::AtomicU64::new(1);
+                let (node, env) = (node_id.clone(), environment.clone());
+                let attach_guest_output = settings.attach_guest_output;
+                let prove: connection::ProveFn = Arc::new(move |request, cancelled| {
+                    let result = streamed_proving(request, attach_guest_output, cancelled);
+                    if result.is_ok() {
+                        utils::updater::confirm_update_health();
+                    }
+                    let proof_count =
+                        proof_count.fetch_add(1, std::sync::atomic::Ordering::Relaxed) + 1;
+                    analytics::track(
+                        "cli_proof_node_v2".to_string(),
+                        format!("Completed proof iteration #{}", proof_count),
+                        serde_json::json!({
+                            "node_id": node,
+                            "proof_count": proof_count,
+                        }),
+                        false,
+                        &env,
+                        client_id.clone(),
+                    );
+                    result
+                });
+
+                let url = connection::stream_url(environment);
+                println!("{}: {}", "Streaming tasks from".bold(), url.bright_cyan());
+                let registration = connection::registration(&node_id, environment);
+                connection::run(&url, registration, prove, pool, network, tls_config).await;
+                return Ok(());
+            }
+
+            let client = OrchestratorClient::new(environment.clone(), network, &tls_config)?;
             let mut proof_count = 1;
 
             loop {
@@ -262,10 +575,14 @@ pub async fn start_prover(
                 let mut success = false;
 
                 while attempt <= max_attempts {
-                    println!("Attempt #{} for authenticated proving (node_id={})", attempt, node_id);
-                    match authenticated_proving(&node_id, environment).await {
+                    println!(
+                        "Attempt #{} for authenticated proving (node_id={})",
+                        attempt, node_id
+                    );
+                    match authenticated_proving(&node_id, &client, &pool, settings).await {
                         Ok(_) => {
                             println!("Proving succeeded on attempt #{attempt}!");
+                            utils::updater::confirm_update_health();
                             success = true;
                             break;
                         }
@@ -308,4 +625,4 @@ pub async fn start_prover(
             Err("Invalid setup option selected".into())
         }
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,503 @@
+//! Proving off the async runtime.
+//!
+//! Proofs run on a small pool of dedicated threads instead of tokio workers, so a long
+//! proof never stalls heartbeats, lease renewals or uploads. Every job gets a
+//! cancellation token, which is cancelled:
+//! - when the job runs past the per-task timeout
+//! - when the caller stops waiting for it, e.g. because the task's lease expired
+//! - when the node shuts down
+//! - when the user aborts the current proof with `SIGUSR1`
+//!
+//! The caller stops waiting for a cancelled job right away. The zkVM prover cannot be
+//! interrupted, so proofs run in a child process of the node (see [`run_child`]),
+//! which is killed as soon as the job's token is cancelled. Other jobs check their token
+//! between steps, and keep their worker until they do. Meanwhile the jobs behind them
+//! wait in the queue, so:
+//! - a job's timeout only starts once a worker picks it up
+//! - callers that fetch tasks wait for a free worker first (see
+//!   [`ProverPool::wait_for_worker`]), so a task's lease does not run out while it
+//!   is queued behind a job that is still stopping
+
+use crate::telemetry::LogLevel;
+use clap::ValueEnum;
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use std::io::{Read, Write};
+use std::panic::AssertUnwindSafe;
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+use std::sync::{mpsc, Arc};
+use std::time::Duration;
+use tokio::sync::{oneshot, watch};
+use tokio_util::sync::CancellationToken;
+
+/// How often a job checks on its child process, and on its token meanwhile
+const CHILD_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Proving preferences, read from the `proving` section of the settings file.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ProvingSettings {
+    /// Seconds a single proof may take before it is abandoned
+    pub timeout_secs: u64,
+    /// Number of proofs that can run at once
+    pub workers: usize,
+    /// Where to keep a copy of every submitted proof and its guest output
+    pub archive_dir: Option<PathBuf>,
+    /// Attach what the guest program printed to submissions
+    pub attach_guest_output: bool,
+}
+
+impl Default for ProvingSettings {
+    fn default() -> Self {
+        Self {
+            timeout_secs: 60 * 60,
+            workers: 1,
+            archive_dir: None,
+            attach_guest_output: false,
+        }
+    }
+}
+
+impl ProvingSettings {
+    /// Describe the proving configuration, for `doctor`
+    pub fn describe(&self) -> String {
+        let mut description = format!(
+            "timeout {}s, {} worker(s)",
+            self.timeout_secs,
+            self.workers.max(1)
+        );
+        if let Some(dir) = &self.archive_dir {
+            description.push_str(&format!(", archived to {}", dir.display()));
+        }
+        if self.attach_guest_output {
+            description.push_str(", guest output attached");
+        }
+        description
+    }
+}
+
+/// Why a job did not produce a result
+#[derive(Debug)]
+pub enum Stopped {
+    /// Cancelled by shutdown, by the user, or because its result was no longer wanted
+    Cancelled,
+    /// Ran longer than the per-task timeout
+    TimedOut(Duration),
+    /// Panicked on its worker
+    Crashed(String),
+}
+
+impl std::fmt::Display for Stopped {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Stopped::Cancelled => write!(f, "Proof was cancelled"),
+            Stopped::TimedOut(timeout) => {
+                write!(f, "Proof did not finish within {}s", timeout.as_secs())
+            }
+            Stopped::Crashed(message) => write!(f, "Prover crashed: {}", message),
+        }
+    }
+}
+
+impl std::error::Error for Stopped {}
+
+type Job = Box<dyn FnOnce() + Send>;
+
+/// Dedicated threads that run proofs
+pub struct ProverPool {
+    jobs: mpsc::Sender<Job>,
+    timeout: Duration,
+    /// Parent of every job's token
+    shutdown: CancellationToken,
+    /// Tokens of the jobs currently queued or running
+    running: Mutex<Vec<CancellationToken>>,
+    workers: usize,
+    /// Number of workers busy with a job, including jobs nobody waits for any more
+    busy: Arc<watch::Sender<usize>>,
+}
+
+impl ProverPool {
+    pub fn new(settings: &ProvingSettings) -> Self {
+        let (jobs, queue) = mpsc::channel::<Job>();
+        let queue = Arc::new(std::sync::Mutex::new(queue));
+
+        for worker in 0..settings.workers.max(1) {
+            let queue = queue.clone();
+            std::thread::Builder::new()
+                .name(format!("prover-{}", worker))
+                .spawn(move || loop {
+                    // Only the lock is held while waiting; the job runs without it
+                    let job = queue.lock().map(|queue| queue.recv());
+                    match job {
+                        Ok(Ok(job)) => job(),
+                        _ => return,
+                    }
+                })
+                .expect("Failed to start prover thread");
+        }
+
+        Self {
+            jobs,
+            timeout: Duration::from_secs(settings.timeout_secs),
+            shutdown: CancellationToken::new(),
+            running: Mutex::new(Vec::new()),
+            workers: settings.workers.max(1),
+            busy: Arc::new(watch::Sender::new(0)),
+        }
+    }
+
+    /// Wait until a worker is free to start a job right away.
+    ///
+    /// Workers stay busy with cancelled jobs until they stop, so callers wait here before
+    /// taking on a task with a deadline.
+    pub async fn wait_for_worker(&self) {
+        let mut busy = self.busy.subscribe();
+        if *busy.borrow() >= self.workers {
+            println!("Waiting for an abandoned proof to finish before taking the next task...");
+        }
+        let _ = busy.wait_for(|&busy| busy < self.workers).await;
+    }
+
+    /// Run `job` on the pool and wait for its result.
+    ///
+    /// The job is handed a token to check between steps. Its timeout starts when a
+    /// worker picks it up. Dropping the returned future cancels the job.
+    pub async fn run<T, F>(&self, job: F) -> Result<T, Stopped>
+    where
+        T: Send + 'static,
+        F: FnOnce(&CancellationToken) -> T + Send + 'static,
+    {
+        let token = self.shutdown.child_token();
+        let _guard = CancelOnDrop {
+            pool: self,
+            token: token.clone(),
+        };
+        self.running.lock().push(token.clone());
+
+        let (started_tx, started_rx) = oneshot::channel();
+        let (result_tx, result_rx) = oneshot::channel();
+        let job_token = token.clone();
+        let busy = self.busy.clone();
+        let job: Job = Box::new(move || {
+            // Jobs cancelled while queued never start; dropping the sender reports it
+            if job_token.is_cancelled() {
+                return;
+            }
+            busy.send_modify(|busy| *busy += 1);
+            let _ = started_tx.send(());
+            let result = std::panic::catch_unwind(AssertUnwindSafe(|| job(&job_token)));
+            busy.send_modify(|busy| *busy -= 1);
+            let _ = result_tx.send(result.map_err(panic_message));
+        });
+        self.jobs.send(job).map_err(|_| Stopped::Cancelled)?;
+
+        let timeout = async {
+            // A job that never starts is reported through its result instead
+            if started_rx.await.is_ok() {
+                tokio::time::sleep(self.timeout).await;
+            } else {
+                std::future::pending::<()>().await;
+            }
+        };
+
+        tokio::select! {
+            biased;
+            result = result_rx => match result {
+                Ok(Ok(output)) => Ok(output),
+                Ok(Err(message)) => Err(Stopped::Crashed(message)),
+                Err(_) => Err(Stopped::Cancelled),
+            },
+            _ = token.cancelled() => Err(Stopped::Cancelled),
+            _ = timeout => Err(Stopped::TimedOut(self.timeout)),
+        }
+    }
+
+    /// Cancel every queued or running job, returning how many there were
+    pub fn abort(&self) -> usize {
+        let running = self.running.lock();
+        for token in running.iter() {
+            token.cancel();
+        }
+        running.len()
+    }
+
+    /// Cancel every job, and refuse new ones
+    pub fn shutdown(&self) {
+        self.shutdown.cancel();
+    }
+}
+
+/// Cancels a job once nobody waits for it any more, whether it finished or not
+struct CancelOnDrop<'a> {
+    pool: &'a ProverPool,
+    token: CancellationToken,
+}
+
+impl Drop for CancelOnDrop<'_> {
+    fn drop(&mut self) {
+        self.token.cancel();
+        self.pool
+            .running
+            .lock()
+            .retain(|token| !token.is_cancelled());
+    }
+}
+
+fn panic_message(panic: Box<dyn std::any::Any + Send>) -> String {
+    panic
+        .downcast_ref::<&str>()
+        .map(|message| message.to_string())
+        .or_else(|| panic.downcast_ref::<String>().cloned())
+        .unwrap_or_else(|| "unknown panic".to_string())
+}
+
+/// Run this binary's hidden `prove-job` command in a child process, handing it `request`
+/// on stdin, and return what it wrote to stdout.
+///
+/// The child logs to stderr at the level this process does. It is killed as soon as
+/// `cancelled` is, so a worker never waits for a proof nobody wants.
+pub fn run_child(request: &[u8], cancelled: &CancellationToken) -> Result<Vec<u8>, String> {
+    let exe = std::env::current_exe()
+        .map_err(|e| format!("Failed to find the node's executable: {}", e))?;
+    let log_level = LogLevel::current();
+    let mut command = Command::new(exe);
+    command.arg("prove-job");
+    if let Some(level) = log_level.to_possible_value() {
+        command.args(["--log-level", level.get_name()]);
+    }
+    run_process(&mut command, request, cancelled)
+}
+
+fn run_process(
+    command: &mut Command,
+    request: &[u8],
+    cancelled: &CancellationToken,
+) -> Result<Vec<u8>, String> {
+    let mut child = command
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .map_err(|e| format!("Failed to start the prover: {}", e))?;
+
+    // Written and read on their own threads, so neither pipe fills up while waiting
+    let (mut stdin, mut stdout) = (child.stdin.take(), child.stdout.take());
+    let request = request.to_vec();
+    let writer = std::thread::spawn(move || stdin.as_mut().map(|stdin| stdin.write_all(&request)));
+    let reader = std::thread::spawn(move || {
+        let mut response = Vec::new();
+        stdout
+            .as_mut()
+            .map(|stdout| stdout.read_to_end(&mut response))
+            .transpose()
+            .map(|_| response)
+    });
+
+    let status = loop {
+        if cancelled.is_cancelled() {
+            let _ = child.kill();
+            let _ = child.wait();
+            return Err("Proof was cancelled".to_string());
+        }
+        match child.try_wait() {
+            Ok(Some(status)) => break status,
+            Ok(None) => std::thread::sleep(CHILD_POLL_INTERVAL),
+            Err(e) => return Err(format!("Failed to wait for the prover: {}", e)),
+        }
+    };
+    let _ = writer.join();
+    let response = reader
+        .join()
+        .map_err(|_| "Failed to read from the prover".to_string())?
+        .map_err(|e| format!("Failed to read from the prover: {}", e))?;
+    if !status.success() {
+        return Err(format!("Prover process failed ({})", status));
+    }
+    Ok(response)
+}
+
+/// Abort the proofs in progress whenever the process receives `SIGUSR1`
+#[cfg(unix)]
+pub fn spawn_abort_listener(pool: Arc<ProverPool>) {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut signals = match signal(SignalKind::user_defined1()) {
+        Ok(signals) => signals,
+        Err(e) => {
+            eprintln!("Failed to listen for SIGUSR1: {}", e);
+            return;
+        }
+    };
+    tokio::spawn(async move {
+        while signals.recv().await.is_some() {
+            let aborted = pool.abort();
+            println!(
+                "Received SIGUSR1; aborting {} proof(s) in progress",
+                aborted
+            );
+        }
+    });
+}
+
+#[cfg(not(unix))]
+pub fn spawn_abort_listener(_pool: Arc<ProverPool>) {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    fn pool(timeout_secs: u64) -> Arc<ProverPool> {
+        Arc::new(ProverPool::new(&ProvingSettings {
+            timeout_secs,
+            ..ProvingSettings::default()
+        }))
+    }
+
+    /// A job that keeps working until its token is cancelled, recording when it starts
+    /// and when it sees the cancellation
+    fn runaway_job(
+        started: Arc<AtomicBool>,
+        stopped: Arc<AtomicBool>,
+    ) -> impl FnOnce(&CancellationToken) + Send {
+        move |cancelled: &CancellationToken| {
+            started.store(true, Ordering::SeqCst);
+            while !cancelled.is_cancelled() {
+                std::thread::sleep(Duration::from_millis(5));
+            }
+            stopped.store(true, Ordering::SeqCst);
+        }
+    }
+
+    async fn wait_for(flag: &AtomicBool) {
+        for _ in 0..200 {
+            if flag.load(Ordering::SeqCst) {
+                return;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        panic!("job never got there");
+    }
+
+    #[tokio::test]
+    async fn test_jobs_run_off_the_runtime() {
+        let pool = pool(10);
+        let thread = pool
+            .run(|_| std::thread::current().name().map(str::to_string))
+            .await
+            .unwrap();
+        assert_eq!(thread.as_deref(), Some("prover-0"));
+    }
+
+    #[tokio::test]
+    async fn test_timeout_cancels_runaway_job() {
+        let pool = pool(0);
+        let stopped = Arc::new(AtomicBool::new(false));
+
+        let result = pool.run(runaway_job(Arc::default(), stopped.clone())).await;
+
+        assert!(matches!(result, Err(Stopped::TimedOut(_))));
+        wait_for(&stopped).await;
+        // The worker is free again
+        assert_eq!(pool.run(|_| 42).await.unwrap(), 42);
+    }
+
+    #[tokio::test]
+    async fn test_abort_and_dropped_caller_cancel_job() {
+        let pool = pool(60);
+
+        let (started, aborted) = (Arc::default(), Arc::new(AtomicBool::new(false)));
+        let running = tokio::spawn({
+            let pool = pool.clone();
+            let job = runaway_job(Arc::clone(&started), aborted.clone());
+            async move { pool.run(job).await }
+        });
+        wait_for(&started).await;
+        assert_eq!(pool.abort(), 1);
+        assert!(matches!(running.await.unwrap(), Err(Stopped::Cancelled)));
+        wait_for(&aborted).await;
+
+        // As when a lease expires: the caller gives up on the proof
+        let (started, abandoned) = (Arc::default(), Arc::new(AtomicBool::new(false)));
+        let waiting = tokio::spawn({
+            let pool = pool.clone();
+            let job = runaway_job(Arc::clone(&started), abandoned.clone());
+            async move { pool.run(job).await }
+        });
+        wait_for(&started).await;
+        waiting.abort();
+        wait_for(&abandoned).await;
+    }
+
+    #[tokio::test]
+    async fn test_timeout_starts_when_the_job_starts() {
+        let pool = pool(1);
+        let (started, stopped) = (Arc::new(AtomicBool::new(false)), Arc::default());
+        let release = Arc::new(AtomicBool::new(false));
+
+        // A cancelled job that ignores its token keeps the only worker busy
+        let stuck = tokio::spawn({
+            let pool = pool.clone();
+            let (started, release) = (started.clone(), release.clone());
+            async move {
+                pool.run(move |_| {
+                    started.store(true, Ordering::SeqCst);
+                    while !release.load(Ordering::SeqCst) {
+                        std::thread::sleep(Duration::from_millis(5));
+                    }
+                })
+                .await
+            }
+        });
+        wait_for(&started).await;
+        stuck.abort();
+        assert!(
+            tokio::time::timeout(Duration::from_millis(100), pool.wait_for_worker())
+                .await
+                .is_err()
+        );
+
+        // Queued for longer than the timeout, but not timed out until it ran that long
+        let queued = tokio::spawn({
+            let pool = pool.clone();
+            let job = runaway_job(Arc::default(), stopped);
+            async move { pool.run(job).await }
+        });
+        tokio::time::sleep(Duration::from_millis(1500)).await;
+        assert!(!queued.is_finished());
+        release.store(true, Ordering::SeqCst);
+        pool.wait_for_worker().await;
+        assert!(matches!(queued.await.unwrap(), Err(Stopped::TimedOut(_))));
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_cancelling_a_child_process_kills_it() {
+        let cancelled = CancellationToken::new();
+        let started = std::time::Instant::now();
+        std::thread::spawn({
+            let cancelled = cancelled.clone();
+            move || {
+                std::thread::sleep(Duration::from_millis(100));
+                cancelled.cancel();
+            }
+        });
+        let result = run_process(Command::new("sleep").arg("60"), &[], &cancelled);
+        assert!(result.is_err());
+        assert!(started.elapsed() < Duration::from_secs(10));
+
+        let echoed = run_process(
+            &mut Command::new("cat"),
+            b"request",
+            &CancellationToken::new(),
+        );
+        assert_eq!(echoed.unwrap(), b"request");
+    }
+
+    #[tokio::test]
+    async fn test_panicking_job_is_reported() {
+        let pool = pool(10);
+        let result = pool.run(|_| -> () { panic!("guest exploded") }).await;
+        assert!(matches!(result, Err(Stopped::Crashed(message)) if message == "guest exploded"));
+    }
+}
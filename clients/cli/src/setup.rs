@@ -36,12 +36,12 @@ fn save_node_id(node_id: &str) -> std::io::Result<()> {
 
     // Print how to find the config file
     println!("Loading configuration: {}", config_path.to_string_lossy());
-    
+
     // Create the config object
     let config = NodeConfig {
         node_id: node_id.to_string(),
     };
-    
+
     // Write the config to file
     let json = serde_json::to_string_pretty(&config)
         .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
@@ -85,12 +85,10 @@ pub async fn run_initial_setup() -> SetupResult {
     //Check if the node-id file exists, use it. If not, create a new one.
     let node_config_path = home_path.join(".nexus").join("config.json");
     let node_id = match fs::read_to_string(&node_config_path) {
-        Ok(content) => {
-            match serde_json::from_str::<NodeConfig>(&content) {
-                Ok(config) => config.node_id,
-                Err(_) => String::new(),
-            }
-        }
+        Ok(content) => match serde_json::from_str::<NodeConfig>(&content) {
+            Ok(config) => config.node_id,
+            Err(_) => String::new(),
+        },
         Err(_) => String::new(),
     };
 
@@ -161,6 +159,12 @@ pub async fn run_initial_setup() -> SetupResult {
     }
 }
 
+/// The node ID saved by an earlier setup, if any, without prompting for one
+pub fn saved_node_id() -> Option<String> {
+    let home_path = get_home_directory().ok()?;
+    read_existing_node_id(&home_path.join(".nexus").join("config.json")).ok()
+}
+
 pub fn clear_node_id() -> std::io::Result<()> {
     let home_path: std::path::PathBuf =
         home::home_dir().expect("Failed to determine home directory");
@@ -0,0 +1,171 @@
+//! Runtime state of the node: the running version, its PID and pending updates.
+//!
+//! Everything lives in one directory, `$XDG_STATE_HOME/nexus` if that is set and
+//! `~/.nexus/state` otherwise, no matter where the CLI was started from. A lock file
+//! in that directory keeps two nodes from sharing (and clobbering) the same state.
+
+use fs2::FileExt;
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+pub const VERSION_FILE: &str = "current_version";
+pub const PID_FILE: &str = "prover.pid";
+pub const UPDATE_PENDING_FILE: &str = "update_pending.json";
+pub const SKIPPED_VERSION_FILE: &str = "skipped_version";
+pub const BENCHMARK_FILE: &str = "benchmark.json";
+pub const ANALYTICS_SPOOL_FILE: &str = "analytics_spool.jsonl";
+const LOCK_FILE: &str = "lock";
+
+/// Files that older versions wrote to the current working directory, and their new names
+const LEGACY_FILES: [(&str, &str); 2] = [
+    (".current_version", VERSION_FILE),
+    (".prover.pid", PID_FILE),
+];
+
+/// How long to wait for the lock, e.g. while the previous process hands over after an update
+const LOCK_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// The directory holding all runtime state
+pub fn state_dir() -> PathBuf {
+    let xdg_state_home = std::env::var_os("XDG_STATE_HOME")
+        .map(PathBuf::from)
+        .filter(|dir| dir.is_absolute());
+    if let Some(dir) = xdg_state_home {
+        return dir.join("nexus");
+    }
+
+    home::home_dir()
+        .map(|home| home.join(".nexus").join("state"))
+        .unwrap_or_else(|| PathBuf::from(".nexus-state"))
+}
+
+/// Path of a file in the state directory
+pub fn path(name: &str) -> PathBuf {
+    state_dir().join(name)
+}
+
+/// Exclusive hold on the state directory, released when dropped or when the process exits
+pub struct StateLock {
+    _file: File,
+}
+
+impl StateLock {
+    /// Lock the state directory, creating it and moving in files left by older versions
+    pub fn acquire() -> Result<Self, Box<dyn std::error::Error>> {
+        let dir = state_dir();
+        let lock = Self::acquire_in(&dir, LOCK_TIMEOUT)?;
+        migrate_legacy_files(Path::new("."), &dir);
+        Ok(lock)
+    }
+
+    fn acquire_in(dir: &Path, timeout: Duration) -> Result<Self, Box<dyn std::error::Error>> {
+        fs::create_dir_all(dir)?;
+        let path = dir.join(LOCK_FILE);
+        let mut file = OpenOptions::new()
+            .create(true)
+            .read(true)
+            .write(true)
+            .truncate(false)
+            .open(&path)?;
+
+        let started = Instant::now();
+        while file.try_lock_exclusive().is_err() {
+            if started.elapsed() >= timeout {
+                let holder = fs::read_to_string(&path).unwrap_or_default();
+                return Err(format!(
+                    "{} is in use by another nexus-network process (PID {}). \
+                     Stop it first, or set XDG_STATE_HOME to give this node its own state.",
+                    dir.to_string_lossy(),
+                    holder.trim()
+                )
+                .into());
+            }
+            std::thread::sleep(Duration::from_millis(250));
+        }
+
+        // Record the holder so the error above can name it
+        file.set_len(0)?;
+        write!(file, "{}", std::process::id())?;
+
+        Ok(Self { _file: file })
+    }
+}
+
+/// Move state files written to `from` by older versions into `to`.
+///
+/// Where both exist, the file already in the state directory wins.
+fn migrate_legacy_files(from: &Path, to: &Path) {
+    for (legacy_name, name) in LEGACY_FILES {
+        let legacy = from.join(legacy_name);
+        if !legacy.is_file() {
+            continue;
+        }
+
+        let target = to.join(name);
+        let moved = !target.exists();
+        // Copy rather than rename, as the state directory may be on another filesystem
+        let result = if moved {
+            fs::copy(&legacy, &target).and_then(|_| fs::remove_file(&legacy))
+        } else {
+            fs::remove_file(&legacy)
+        };
+
+        match result {
+            Ok(()) if moved => println!(
+                "Moved {} to {}",
+                legacy.to_string_lossy(),
+                target.to_string_lossy()
+            ),
+            Ok(()) => {}
+            Err(e) => eprintln!("Failed to migrate {}: {}", legacy.to_string_lossy(), e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("nexus-state-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn test_second_lock_is_refused_until_first_is_dropped() {
+        let dir = temp_dir("lock");
+
+        let first = StateLock::acquire_in(&dir, Duration::ZERO).unwrap();
+        let error = StateLock::acquire_in(&dir, Duration::ZERO).err().unwrap();
+        assert!(error.to_string().contains(&std::process::id().to_string()));
+
+        drop(first);
+        assert!(StateLock::acquire_in(&dir, Duration::ZERO).is_ok());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_legacy_files_are_moved_into_state_dir() {
+        let cwd = temp_dir("legacy");
+        let state = cwd.join("state");
+        fs::create_dir_all(&state).unwrap();
+        fs::write(cwd.join(".current_version"), "0.8.0").unwrap();
+        fs::write(cwd.join(".prover.pid"), "123").unwrap();
+        fs::write(state.join(PID_FILE), "456").unwrap();
+
+        migrate_legacy_files(&cwd, &state);
+
+        assert_eq!(
+            fs::read_to_string(state.join(VERSION_FILE)).unwrap(),
+            "0.8.0"
+        );
+        assert_eq!(fs::read_to_string(state.join(PID_FILE)).unwrap(), "456");
+        assert!(!cwd.join(".current_version").exists());
+        assert!(!cwd.join(".prover.pid").exists());
+        fs::remove_dir_all(&cwd).unwrap();
+    }
+}
@@ -0,0 +1,227 @@
+//! OpenTelemetry tracing for the proving pipeline, and log output.
+//!
+//! Spans are recorded with the `tracing` crate and exported through OpenTelemetry,
+//! either over OTLP/HTTP to a collector or as JSON lines on stdout for local testing.
+//! Log records, from `log` and `tracing` alike, are printed to stderr when they are at
+//! or above the configured level.
+
+use opentelemetry::trace::TracerProvider as _;
+use opentelemetry::KeyValue;
+use opentelemetry_otlp::WithExportConfig;
+use opentelemetry_sdk::export::trace::{ExportResult, SpanData, SpanExporter};
+use opentelemetry_sdk::trace::TracerProvider;
+use opentelemetry_sdk::{runtime, Resource};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::future::Future;
+use std::pin::Pin;
+use tracing_subscriber::filter::LevelFilter;
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::Layer as _;
+
+const SERVICE_NAME: &str = "nexus-network";
+const DEFAULT_OTLP_ENDPOINT: &str = "http://localhost:4318";
+const OTLP_TRACES_PATH: &str = "/v1/traces";
+
+/// Where trace spans are exported.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum TraceExporter {
+    /// Do not record traces
+    #[default]
+    None,
+    /// Send spans to an OpenTelemetry collector over OTLP/HTTP
+    Otlp,
+    /// Print spans to stdout as JSON lines
+    Stdout,
+}
+
+/// Lowest level of log records printed to stderr.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum LogLevel {
+    Off,
+    Error,
+    #[default]
+    Warn,
+    Info,
+    /// Also shows the output of guest programs
+    Debug,
+    Trace,
+}
+
+impl From<LogLevel> for LevelFilter {
+    fn from(level: LogLevel) -> Self {
+        match level {
+            LogLevel::Off => LevelFilter::OFF,
+            LogLevel::Error => LevelFilter::ERROR,
+            LogLevel::Warn => LevelFilter::WARN,
+            LogLevel::Info => LevelFilter::INFO,
+            LogLevel::Debug => LevelFilter::DEBUG,
+            LogLevel::Trace => LevelFilter::TRACE,
+        }
+    }
+}
+
+impl LogLevel {
+    /// The level log records are currently printed at
+    pub fn current() -> Self {
+        match LevelFilter::current() {
+            LevelFilter::OFF => LogLevel::Off,
+            LevelFilter::ERROR => LogLevel::Error,
+            LevelFilter::WARN => LogLevel::Warn,
+            LevelFilter::INFO => LogLevel::Info,
+            LevelFilter::DEBUG => LogLevel::Debug,
+            _ => LogLevel::Trace,
+        }
+    }
+}
+
+/// Tracing preferences, read from the `tracing` section of the settings file.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TracingSettings {
+    /// Where spans are exported.
+    pub exporter: TraceExporter,
+    /// Collector URL for the `otlp` exporter. Defaults to `http://localhost:4318`.
+    pub otlp_endpoint: Option<String>,
+    /// Lowest level of log records printed to stderr.
+    pub log_level: LogLevel,
+}
+
+/// Install the global tracing subscriber.
+///
+/// Returns the provider, which must be shut down before exit so buffered spans are exported.
+pub fn init(
+    settings: &TracingSettings,
+) -> Result<Option<TracerProvider>, Box<dyn std::error::Error>> {
+    let resource = Resource::new(vec![
+        KeyValue::new("service.name", SERVICE_NAME),
+        KeyValue::new("service.version", env!("CARGO_PKG_VERSION")),
+    ]);
+
+    let provider = match settings.exporter {
+        TraceExporter::None => None,
+        TraceExporter::Otlp => {
+            let exporter = opentelemetry_otlp::SpanExporter::builder()
+                .with_http()
+                .with_endpoint(otlp_traces_url(settings.otlp_endpoint.as_deref()))
+                .build()?;
+            Some(
+                TracerProvider::builder()
+                    .with_batch_exporter(exporter, runtime::Tokio)
+                    .with_resource(resource)
+                    .build(),
+            )
+        }
+        TraceExporter::Stdout => Some(
+            TracerProvider::builder()
+                .with_simple_exporter(StdoutExporter)
+                .with_resource(resource)
+                .build(),
+        ),
+    };
+
+    let spans = provider
+        .as_ref()
+        .map(|provider| tracing_opentelemetry::layer().with_tracer(provider.tracer(SERVICE_NAME)));
+    let logs = tracing_subscriber::fmt::layer()
+        .with_writer(std::io::stderr)
+        .with_filter(LevelFilter::from(settings.log_level));
+    tracing_subscriber::registry()
+        .with(spans)
+        .with(logs)
+        .try_init()?;
+
+    Ok(provider)
+}
+
+/// Export any remaining spans and stop the exporter
+pub fn shutdown(provider: Option<TracerProvider>) {
+    if let Some(provider) = provider {
+        if let Err(e) = provider.shutdown() {
+            eprintln!("Failed to export remaining trace spans: {}", e);
+        }
+    }
+}
+
+/// Describe the tracing configuration, for `doctor`
+pub fn describe(settings: &TracingSettings) -> String {
+    let exporter = match settings.exporter {
+        TraceExporter::None => "disabled".to_string(),
+        TraceExporter::Otlp => format!(
+            "otlp ({})",
+            otlp_traces_url(settings.otlp_endpoint.as_deref())
+        ),
+        TraceExporter::Stdout => "stdout".to_string(),
+    };
+    let log_level = LevelFilter::from(settings.log_level);
+    format!("{}, log level {}", exporter, log_level)
+}
+
+/// Accept either a collector base URL or the full traces URL
+fn otlp_traces_url(endpoint: Option<&str>) -> String {
+    let endpoint = endpoint.unwrap_or(DEFAULT_OTLP_ENDPOINT);
+    if endpoint.trim_end_matches('/').ends_with(OTLP_TRACES_PATH) {
+        endpoint.to_string()
+    } else {
+        format!("{}{}", endpoint.trim_end_matches('/'), OTLP_TRACES_PATH)
+    }
+}
+
+/// Prints each finished span as a JSON line on stdout
+#[derive(Debug)]
+struct StdoutExporter;
+
+impl SpanExporter for StdoutExporter {
+    fn export(
+        &mut self,
+        batch: Vec<SpanData>,
+    ) -> Pin<Box<dyn Future<Output = ExportResult> + Send + 'static>> {
+        for span in batch {
+            let duration_ms = span
+                .end_time
+                .duration_since(span.start_time)
+                .unwrap_or_default()
+                .as_secs_f64()
+                * 1000.0;
+            let attributes: serde_json::Map<String, serde_json::Value> = span
+                .attributes
+                .iter()
+                .map(|kv| (kv.key.to_string(), json!(kv.value.to_string())))
+                .collect();
+
+            println!(
+                "{}",
+                json!({
+                    "trace_id": span.span_context.trace_id().to_string(),
+                    "span_id": span.span_context.span_id().to_string(),
+                    "parent_span_id": span.parent_span_id.to_string(),
+                    "name": span.name,
+                    "duration_ms": duration_ms,
+                    "attributes": attributes,
+                })
+            );
+        }
+        Box::pin(std::future::ready(Ok(())))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_otlp_traces_url() {
+        assert_eq!(otlp_traces_url(None), "http://localhost:4318/v1/traces");
+        assert_eq!(
+            otlp_traces_url(Some("https://otel.example.com/")),
+            "https://otel.example.com/v1/traces"
+        );
+        assert_eq!(
+            otlp_traces_url(Some("https://otel.example.com/v1/traces")),
+            "https://otel.example.com/v1/traces"
+        );
+    }
+}
@@ -0,0 +1,371 @@
+//! TLS configuration shared by every connection to the orchestrator.
+//!
+//! Certificates are always verified, against the public web PKI or a custom CA bundle
+//! for a self-hosted orchestrator. Optionally the server's public key must also match
+//! one of a set of pinned SPKI hashes. Verification can only be switched off with
+//! `--insecure`, which is meant for local testing and is reported loudly.
+
+use base64::Engine as _;
+use colored::Colorize;
+use rustls::client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier};
+use rustls::client::WebPkiServerVerifier;
+use rustls::crypto::{self, CryptoProvider};
+use rustls::pki_types::{CertificateDer, ServerName, UnixTime};
+use rustls::{ClientConfig, DigitallySignedStruct, RootCertStore, SignatureScheme};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::path::PathBuf;
+use std::sync::Arc;
+
+/// TLS preferences, read from the `tls` section of the settings file.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TlsSettings {
+    /// PEM file with the CA certificates to trust instead of the public web PKI.
+    pub ca_bundle: Option<PathBuf>,
+    /// SHA-256 hashes of the server's SubjectPublicKeyInfo, base64-encoded and
+    /// optionally prefixed with `sha256/`. When set, the server key must match one.
+    pub spki_pins: Vec<String>,
+    /// Accept any certificate. Only for testing against a local orchestrator.
+    pub insecure: bool,
+}
+
+/// Build the client configuration for orchestrator connections
+pub fn client_config(
+    settings: &TlsSettings,
+) -> Result<Arc<ClientConfig>, Box<dyn std::error::Error>> {
+    let provider = Arc::new(crypto::ring::default_provider());
+    let builder = ClientConfig::builder_with_provider(provider.clone())
+        .with_safe_default_protocol_versions()?
+        .dangerous();
+
+    if settings.insecure {
+        eprintln!(
+            "{}",
+            "WARNING: TLS certificate verification is DISABLED (--insecure). \
+             Connections to the orchestrator can be intercepted. Use this only for local testing."
+                .red()
+                .bold()
+        );
+        let verifier = Arc::new(AcceptAnyCertificate { provider });
+        return Ok(Arc::new(
+            builder
+                .with_custom_certificate_verifier(verifier)
+                .with_no_client_auth(),
+        ));
+    }
+
+    let mut roots = RootCertStore::empty();
+    match &settings.ca_bundle {
+        Some(path) => {
+            let pem = std::fs::read(path)
+                .map_err(|e| format!("Failed to read CA bundle {}: {}", path.display(), e))?;
+            for cert in rustls_pemfile::certs(&mut pem.as_slice()) {
+                roots.add(cert?)?;
+            }
+            if roots.is_empty() {
+                return Err(
+                    format!("CA bundle {} contains no certificates", path.display()).into(),
+                );
+            }
+        }
+        None => roots.extend(webpki_roots::TLS_SERVER_ROOTS.iter().cloned()),
+    }
+
+    let webpki = WebPkiServerVerifier::builder_with_provider(Arc::new(roots), provider).build()?;
+    let verifier: Arc<dyn ServerCertVerifier> = if settings.spki_pins.is_empty() {
+        webpki
+    } else {
+        let pins = settings
+            .spki_pins
+            .iter()
+            .map(|pin| parse_pin(pin))
+            .collect::<Result<Vec<_>, _>>()?;
+        Arc::new(PinnedVerifier {
+            inner: webpki,
+            pins,
+        })
+    };
+
+    Ok(Arc::new(
+        builder
+            .with_custom_certificate_verifier(verifier)
+            .with_no_client_auth(),
+    ))
+}
+
+/// Describe the TLS configuration, for `doctor`
+pub fn describe(settings: &TlsSettings) -> String {
+    if settings.insecure {
+        return "INSECURE (certificates are not verified)".to_string();
+    }
+    let roots = match &settings.ca_bundle {
+        Some(path) => format!("CA bundle {}", path.display()),
+        None => "public web PKI".to_string(),
+    };
+    match settings.spki_pins.len() {
+        0 => roots,
+        n => format!("{}, {} pinned key(s)", roots, n),
+    }
+}
+
+/// Decode a `sha256/<base64>` or bare base64 SPKI pin
+fn parse_pin(pin: &str) -> Result<[u8; 32], String> {
+    let encoded = pin.trim().trim_start_matches("sha256/");
+    base64::engine::general_purpose::STANDARD
+        .decode(encoded)
+        .ok()
+        .and_then(|bytes| bytes.try_into().ok())
+        .ok_or_else(|| format!("Invalid SPKI pin {:?}: expected a base64 SHA-256 hash", pin))
+}
+
+/// SHA-256 of the certificate's SubjectPublicKeyInfo
+fn spki_sha256(cert: &CertificateDer<'_>) -> Result<[u8; 32], rustls::Error> {
+    let (_, parsed) = x509_parser::parse_x509_certificate(cert.as_ref())
+        .map_err(|_| rustls::Error::General("unparseable server certificate".to_string()))?;
+    Ok(Sha256::digest(parsed.tbs_certificate.subject_pki.raw).into())
+}
+
+/// Verifies the chain as usual, then requires the server key to match a pin
+#[derive(Debug)]
+struct PinnedVerifier {
+    inner: Arc<WebPkiServerVerifier>,
+    pins: Vec<[u8; 32]>,
+}
+
+impl ServerCertVerifier for PinnedVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        intermediates: &[CertificateDer<'_>],
+        server_name: &ServerName<'_>,
+        ocsp_response: &[u8],
+        now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        let verified = self.inner.verify_server_cert(
+            end_entity,
+            intermediates,
+            server_name,
+            ocsp_response,
+            now,
+        )?;
+
+        if self.pins.contains(&spki_sha256(end_entity)?) {
+            Ok(verified)
+        } else {
+            Err(rustls::Error::General(
+                "server key does not match any pinned SPKI hash".to_string(),
+            ))
+        }
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        self.inner.verify_tls12_signature(message, cert, dss)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        self.inner.verify_tls13_signature(message, cert, dss)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.inner.supported_verify_schemes()
+    }
+}
+
+/// Accepts any certificate, while still checking handshake signatures
+#[derive(Debug)]
+struct AcceptAnyCertificate {
+    provider: Arc<CryptoProvider>,
+}
+
+impl ServerCertVerifier for AcceptAnyCertificate {
+    fn verify_server_cert(
+        &self,
+        _end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        _server_name: &ServerName<'_>,
+        _ocsp_response: &[u8],
+        _now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        Ok(ServerCertVerified::assertion())
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        crypto::verify_tls12_signature(
+            message,
+            cert,
+            dss,
+            &self.provider.signature_verification_algorithms,
+        )
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        crypto::verify_tls13_signature(
+            message,
+            cert,
+            dss,
+            &self.provider.signature_verification_algorithms,
+        )
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.provider
+            .signature_verification_algorithms
+            .supported_schemes()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rcgen::{BasicConstraints, CertificateParams, IsCa, KeyPair};
+    use rustls::pki_types::PrivateKeyDer;
+    use tokio::io::AsyncWriteExt;
+    use tokio::net::TcpListener;
+
+    /// A locally generated CA and a `localhost` certificate it signed
+    struct TestPki {
+        ca_pem: String,
+        server_cert: CertificateDer<'static>,
+        server_key: Vec<u8>,
+    }
+
+    fn test_pki() -> TestPki {
+        let ca_key = KeyPair::generate().unwrap();
+        let mut ca_params = CertificateParams::new(Vec::<String>::new()).unwrap();
+        ca_params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+        let ca = ca_params.self_signed(&ca_key).unwrap();
+
+        let server_key = KeyPair::generate().unwrap();
+        let server_cert = CertificateParams::new(vec!["localhost".to_string()])
+            .unwrap()
+            .signed_by(&server_key, &ca, &ca_key)
+            .unwrap();
+
+        TestPki {
+            ca_pem: ca.pem(),
+            server_cert: server_cert.der().clone(),
+            server_key: server_key.serialize_der(),
+        }
+    }
+
+    /// Serve a fixed HTTP response over TLS, returning the port
+    async fn serve(pki: &TestPki) -> u16 {
+        let config =
+            rustls::ServerConfig::builder_with_provider(Arc::new(crypto::ring::default_provider()))
+                .with_safe_default_protocol_versions()
+                .unwrap()
+                .with_no_client_auth()
+                .with_single_cert(
+                    vec![pki.server_cert.clone()],
+                    PrivateKeyDer::Pkcs8(pki.server_key.clone().into()),
+                )
+                .unwrap();
+        let acceptor = tokio_rustls::TlsAcceptor::from(Arc::new(config));
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                let acceptor = acceptor.clone();
+                tokio::spawn(async move {
+                    if let Ok(mut tls) = acceptor.accept(stream).await {
+                        let _ = tls
+                            .write_all(
+                                b"HTTP/1.1 200 OK\r\nContent-Length: 2\r\nConnection: close\r\n\r\nok",
+                            )
+                            .await;
+                        let _ = tls.shutdown().await;
+                    }
+                });
+            }
+        });
+        port
+    }
+
+    async fn get(settings: &TlsSettings, port: u16) -> Result<String, Box<dyn std::error::Error>> {
+        let config = client_config(settings)?;
+        let client = reqwest::Client::builder()
+            .use_preconfigured_tls((*config).clone())
+            .build()?;
+        let url = format!("https://localhost:{}/", port);
+        Ok(client.get(url).send().await?.text().await?)
+    }
+
+    fn ca_bundle(pki: &TestPki, name: &str) -> PathBuf {
+        let path =
+            std::env::temp_dir().join(format!("nexus-ca-{}-{}.pem", name, std::process::id()));
+        std::fs::write(&path, &pki.ca_pem).unwrap();
+        path
+    }
+
+    #[tokio::test]
+    async fn test_private_ca_is_rejected_by_default_and_accepted_from_bundle() {
+        let pki = test_pki();
+        let port = serve(&pki).await;
+
+        assert!(get(&TlsSettings::default(), port).await.is_err());
+
+        let settings = TlsSettings {
+            ca_bundle: Some(ca_bundle(&pki, "bundle")),
+            ..Default::default()
+        };
+        assert_eq!(get(&settings, port).await.unwrap(), "ok");
+    }
+
+    #[tokio::test]
+    async fn test_spki_pin_must_match_server_key() {
+        let pki = test_pki();
+        let port = serve(&pki).await;
+        let pin = base64::engine::general_purpose::STANDARD
+            .encode(spki_sha256(&pki.server_cert).unwrap());
+
+        let mut settings = TlsSettings {
+            ca_bundle: Some(ca_bundle(&pki, "pin")),
+            spki_pins: vec![format!("sha256/{}", pin)],
+            insecure: false,
+        };
+        assert_eq!(get(&settings, port).await.unwrap(), "ok");
+
+        settings.spki_pins = vec![base64::engine::general_purpose::STANDARD.encode([0u8; 32])];
+        assert!(get(&settings, port).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_insecure_accepts_unknown_ca() {
+        let pki = test_pki();
+        let port = serve(&pki).await;
+
+        let settings = TlsSettings {
+            insecure: true,
+            ..Default::default()
+        };
+        assert_eq!(get(&settings, port).await.unwrap(), "ok");
+    }
+
+    #[test]
+    fn test_invalid_pin_is_rejected() {
+        assert!(parse_pin("sha256/not-base64!").is_err());
+        assert!(parse_pin(&base64::engine::general_purpose::STANDARD.encode([1u8; 16])).is_err());
+    }
+}
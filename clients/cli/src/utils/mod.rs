@@ -1,3 +1,4 @@
 pub mod cli_branding;
 pub mod experiment;
 pub mod prover;
+pub mod updater;
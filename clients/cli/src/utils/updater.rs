@@ -2,18 +2,53 @@
 //!
 //! This module provides the underlying implementation for:
 //! - Version tracking and persistence
-//! - Git-based version detection
-//! - Update application logic
+//! - Release manifest lookup
+//! - Downloading prebuilt binaries and swapping them in atomically
 //! - Process management for CLI restarts
 //!
-//! The code here is used by the auto-updater thread (./updater.rs) to handle the mechanics of
-//! checking versions and applying updates in both test and production environments.
+//! Updates are prebuilt release artifacts described by a JSON manifest, so nodes
+//! don't need git or a Rust toolchain to stay up to date:
+//!
+//! ```json
+//! {
+//!   "version": "0.8.0",
+//!   "artifacts": {
+//!     "linux-x86_64": {
+//!       "url": "https://.../nexus-network-linux",
+//!       "sha256": "<hex digest of the binary>",
+//!       "signature": "<hex ed25519 signature of \"<version>:<sha256>\">"
+//!     }
+//!   }
+//! }
+//! ```
+//!
+//! Every artifact must match its SHA-256 checksum and carry a valid ed25519 signature
+//! from the release key pinned into this binary at build time. Anything else is refused.
+//! The signature covers the release version along with the checksum, so an older signed
+//! binary cannot be passed off as a newer release.
+//!
+//! Installing keeps the previous binary next to the new one. The new binary must pass a
+//! `self-check` before the switch, and once running it must confirm its health (by finishing
+//! a proof) before a deadline. Otherwise the previous binary is restored and restarted, and
+//! the failed version is skipped by later checks.
+//!
+//! Which release is followed is set in the `updater` section of the settings file:
+//! the `stable` or `beta` channel, or a pinned version. With the `notify-only` policy
+//! the node only reports new releases, for fleets that roll out updates themselves.
 
+use crate::network::{self, NetworkSettings};
+use crate::state;
+use ed25519_dalek::{Signature, Verifier, VerifyingKey};
 use parking_lot::RwLock;
 use semver::Version;
-use std::os::unix::process::CommandExt;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::io::Write;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
-use std::{fs, process::Command};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use std::{fs, process::Command, process::Stdio};
 
 // Constants
 
@@ -21,10 +56,24 @@ use std::{fs, process::Command};
 pub const BLUE: &str = "\x1b[34m"; // Normal blue
 pub const RESET: &str = "\x1b[0m"; // Reset color
 
-// The file to store the current version in
-pub const VERSION_FILE: &str = ".current_version";
-pub const REMOTE_REPO: &str = "https://github.com/nexus-xyz/network-api";
-pub const FALLBACK_VERSION: Version = Version::new(0, 3, 6); // 0.3.6
+pub const DEFAULT_MANIFEST_URL: &str =
+    "https://github.com/nexus-xyz/network-api/releases/latest/download/manifest.json";
+/// Rolling pre-release whose manifest always describes the newest tagged build
+pub const BETA_MANIFEST_URL: &str =
+    "https://github.com/nexus-xyz/network-api/releases/download/beta/manifest.json";
+const RELEASES_URL: &str = "https://github.com/nexus-xyz/network-api/releases/download";
+
+const DOWNLOAD_TIMEOUT: Duration = Duration::from_secs(300);
+/// How long the new binary's `self-check` may take before the update is abandoned
+const SELF_CHECK_TIMEOUT: Duration = Duration::from_secs(10 * 60);
+/// How long a newly started version has to confirm its health before it is rolled back
+const HEALTH_CHECK_DEADLINE: Duration = Duration::from_secs(30 * 60);
+/// How long buffered analytics events may take to send before this process is replaced
+const ANALYTICS_FLUSH_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Hex-encoded ed25519 public key that release artifacts must be signed with.
+/// Builds without it cannot apply updates.
+const UPDATE_PUBLIC_KEY: Option<&str> = option_env!("NEXUS_UPDATE_PUBLIC_KEY");
 
 #[derive(Debug, Clone, Copy, PartialEq, clap::ValueEnum)]
 pub enum AutoUpdaterMode {
@@ -32,43 +81,201 @@ pub enum AutoUpdaterMode {
     Test,
 }
 
+/// Which releases the node follows
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum UpdateChannel {
+    /// Full releases only
+    #[default]
+    Stable,
+    /// Every tagged build, including pre-releases
+    Beta,
+}
+
+/// What the node does when a new release is found
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum UpdatePolicy {
+    /// Install the release and restart into it
+    #[default]
+    Auto,
+    /// Report the release but leave installing it to the operator
+    NotifyOnly,
+    /// Do not check for releases
+    Disabled,
+}
+
+/// Update preferences, read from the `updater` section of the settings file.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct UpdaterSettings {
+    pub channel: UpdateChannel,
+    pub policy: UpdatePolicy,
+    /// Stay on (or move to) exactly this version, regardless of the channel.
+    pub pinned_version: Option<Version>,
+    /// Fetch the release manifest from here instead of the channel's default, e.g. a mirror.
+    pub manifest_url: Option<String>,
+}
+
+impl UpdaterSettings {
+    /// The manifest describing the release this node should run
+    pub fn manifest_url(&self) -> String {
+        if let Some(url) = &self.manifest_url {
+            return url.clone();
+        }
+        match (&self.pinned_version, self.channel) {
+            (Some(version), _) => format!("{}/v{}/manifest.json", RELEASES_URL, version),
+            (None, UpdateChannel::Stable) => DEFAULT_MANIFEST_URL.to_string(),
+            (None, UpdateChannel::Beta) => BETA_MANIFEST_URL.to_string(),
+        }
+    }
+
+    /// One-line summary, for `doctor`
+    pub fn describe(&self) -> String {
+        let policy = match self.policy {
+            UpdatePolicy::Auto => "auto",
+            UpdatePolicy::NotifyOnly => "notify-only",
+            UpdatePolicy::Disabled => return "disabled".to_string(),
+        };
+        let target = match (&self.pinned_version, self.channel) {
+            (Some(version), _) => format!("pinned to {}", version),
+            (None, UpdateChannel::Stable) => "stable channel".to_string(),
+            (None, UpdateChannel::Beta) => "beta channel".to_string(),
+        };
+        format!("{}, {} ({})", policy, target, self.manifest_url())
+    }
+}
+
 /// Struct to manage the updater configuration
 #[derive(Clone)]
 pub struct UpdaterConfig {
-    pub mode: AutoUpdaterMode,
     pub update_interval: u64,
-    pub repo_path: String,
-    pub remote_repo: String,
-    pub hostname: String,
+    pub policy: UpdatePolicy,
+    /// When set, this exact version is installed even if it is older than the running one
+    pub pinned_version: Option<Version>,
+    /// URL of the release manifest describing the latest version
+    pub manifest_url: String,
+    /// The binary that is replaced when an update is applied
+    pub install_path: PathBuf,
+    /// Key that release artifacts must be signed with
+    pub public_key: Option<VerifyingKey>,
+    /// Proxy, timeouts and source address for manifest and binary downloads
+    pub network: NetworkSettings,
 }
 
 impl UpdaterConfig {
-    pub fn new(mode: AutoUpdaterMode, hostname: String) -> Self {
-        match mode {
-            AutoUpdaterMode::Production => Self {
-                mode,
-                repo_path: format!(
-                    "{}/.nexus/network-api",
-                    std::env::var("HOME").unwrap_or_default()
-                ),
-                remote_repo: String::from("https://github.com/nexus-xyz/network-api.git"),
-                update_interval: 3600, // check for updates every 1 hour (3600 seconds)
-                hostname,
-            },
-            AutoUpdaterMode::Test => Self {
-                mode,
-                repo_path: std::env::current_dir()
-                    .expect("Failed to get current directory")
-                    .to_string_lossy()
-                    .into_owned(),
-                remote_repo: String::from("."),
-                update_interval: 30, // check for updates every 30 seconds
-                hostname,
-            },
+    /// Fails if the running binary cannot be located, since there is nothing to replace
+    pub fn new(
+        mode: AutoUpdaterMode,
+        settings: &UpdaterSettings,
+        network: &NetworkSettings,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let install_path = std::env::current_exe()
+            .map_err(|e| format!("Failed to locate the running binary: {}", e))?;
+        let update_interval = match mode {
+            AutoUpdaterMode::Production => 3600, // check for updates every 1 hour (3600 seconds)
+            AutoUpdaterMode::Test => 30,         // check for updates every 30 seconds
+        };
+
+        Ok(Self {
+            update_interval,
+            policy: settings.policy,
+            pinned_version: settings.pinned_version.clone(),
+            manifest_url: settings.manifest_url(),
+            install_path,
+            public_key: pinned_public_key(),
+            network: network.clone(),
+        })
+    }
+}
+
+/// A release as published in the manifest
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReleaseManifest {
+    pub version: Version,
+    /// Prebuilt binaries keyed by platform, e.g. `linux-x86_64`
+    pub artifacts: HashMap<String, ReleaseArtifact>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReleaseArtifact {
+    pub url: String,
+    /// Hex-encoded SHA-256 digest of the binary
+    pub sha256: String,
+    /// Hex-encoded ed25519 signature of [`signed_message`] for this artifact
+    pub signature: String,
+}
+
+/// What the release key signs for an artifact: its version and checksum
+pub fn signed_message(version: &Version, sha256: &str) -> String {
+    format!("{}:{}", version, sha256.trim().to_ascii_lowercase())
+}
+
+impl ReleaseArtifact {
+    /// Check the downloaded binary of release `version` against the manifest checksum
+    /// and the pinned release key
+    pub fn verify(
+        &self,
+        version: &Version,
+        bytes: &[u8],
+        public_key: &VerifyingKey,
+    ) -> Result<(), String> {
+        let digest = format!("{:x}", Sha256::digest(bytes));
+        if !digest.eq_ignore_ascii_case(self.sha256.trim()) {
+            return Err(format!(
+                "checksum mismatch: expected {}, got {}",
+                self.sha256, digest
+            ));
         }
+
+        let signature = hex::decode(self.signature.trim())
+            .ok()
+            .and_then(|bytes| Signature::from_slice(&bytes).ok())
+            .ok_or("signature is not a valid hex-encoded ed25519 signature")?;
+        public_key
+            .verify(signed_message(version, &digest).as_bytes(), &signature)
+            .map_err(|_| {
+                format!(
+                    "signature is not the pinned release key's for version {}",
+                    version
+                )
+            })
+    }
+}
+
+/// The release signing key compiled into this binary, if any
+pub fn pinned_public_key() -> Option<VerifyingKey> {
+    let bytes: [u8; 32] = hex::decode(UPDATE_PUBLIC_KEY?.trim())
+        .ok()?
+        .try_into()
+        .ok()?;
+    VerifyingKey::from_bytes(&bytes).ok()
+}
+
+impl ReleaseManifest {
+    /// The artifact for the platform this binary was built for
+    pub fn artifact_for_this_platform(&self) -> Option<&ReleaseArtifact> {
+        self.artifacts.get(&platform_key())
     }
 }
 
+/// Key identifying this platform in the release manifest
+pub fn platform_key() -> String {
+    format!("{}-{}", std::env::consts::OS, std::env::consts::ARCH)
+}
+
+/// Written before restarting into a new version, removed once that version is healthy
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PendingUpdate {
+    pub previous_version: Version,
+    pub new_version: Version,
+    pub install_path: PathBuf,
+    /// Unix time in seconds by which the new version must confirm its health, set when
+    /// it first starts
+    #[serde(default)]
+    pub deadline: Option<u64>,
+}
+
 pub enum VersionStatus {
     UpdateAvailable(Version), // in case there is an update available, there is a semver `Version` type
     UpToDate,
@@ -79,224 +286,484 @@ pub enum VersionStatus {
 pub struct VersionManager {
     current_version: Arc<RwLock<Version>>,
     config: UpdaterConfig,
+    client: reqwest::Client,
 }
 
 impl VersionManager {
     /// Initialize the version manager
     pub fn new(config: UpdaterConfig) -> Result<Self, Box<dyn std::error::Error>> {
-        let current_version = Arc::new(RwLock::new(
-            read_version_from_file().unwrap_or(FALLBACK_VERSION),
-        ));
+        let current_version = Arc::new(RwLock::new(Version::parse(env!("CARGO_PKG_VERSION"))?));
+        let client = network::client_builder(&config.network)?
+            .timeout(DOWNLOAD_TIMEOUT)
+            .build()?;
         Ok(Self {
             current_version,
             config,
+            client,
         })
     }
 
-    /// Fetch the current version of the CLI and persist it to a file
-    pub fn fetch_and_persist_cli_version(&self) -> Result<Version, Box<dyn std::error::Error>> {
-        // 1. Get the current git tag version (which depends on the updater mode)
-        let current_git_version = self.get_cli_release_version(false)?;
+    /// The version of the CLI that is currently running
+    pub fn current_version(&self) -> Version {
+        self.current_version.read().clone()
+    }
+
+    /// Fetch the release manifest
+    pub async fn fetch_manifest(&self) -> Result<ReleaseManifest, Box<dyn std::error::Error>> {
+        let response = self
+            .client
+            .get(&self.config.manifest_url)
+            .send()
+            .await?
+            .error_for_status()?;
+        Ok(response.json().await?)
+    }
+
+    /// Download the artifact for this platform and swap it in place of the installed binary
+    pub async fn download_and_install(
+        &self,
+        manifest: &ReleaseManifest,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let public_key = self
+            .config
+            .public_key
+            .as_ref()
+            .ok_or("This build has no pinned release key, so updates cannot be verified")?;
+        let artifact = manifest.artifact_for_this_platform().ok_or_else(|| {
+            format!(
+                "Release {} has no binary for {}",
+                manifest.version,
+                platform_key()
+            )
+        })?;
+
+        println!(
+            "{}[auto-updater]{} Downloading version {} from {}...",
+            BLUE, RESET, manifest.version, artifact.url
+        );
+        let bytes = self
+            .client
+            .get(&artifact.url)
+            .send()
+            .await?
+            .error_for_status()?
+            .bytes()
+            .await?;
+
+        if let Err(reason) = artifact.verify(&manifest.version, &bytes, public_key) {
+            log_security_event(&format!(
+                "Refusing update to {} from {}: {}",
+                manifest.version, artifact.url, reason
+            ));
+            return Err(format!("Update verification failed: {}", reason).into());
+        }
 
-        // 2. Convert the semver to a number and write it to a file (so it can persist across updates)
-        write_version_to_file(&current_git_version)?;
+        // Stage the download next to the binary so the final rename stays on one filesystem
+        let staged_path = staging_path(&self.config.install_path);
+        fs::write(&staged_path, &bytes)?;
+        if let Err(e) = make_executable(&staged_path).and_then(|_| {
+            swap_binary(
+                &staged_path,
+                &self.config.install_path,
+                &previous_binary_path(&self.config.install_path),
+            )
+        }) {
+            let _ = fs::remove_file(&staged_path);
+            return Err(e.into());
+        }
 
         println!(
-            "{}[auto-updater]{} Wrote version to file: {}",
-            BLUE, RESET, current_git_version
+            "{}[auto-updater]{} Installed version {} at {}",
+            BLUE,
+            RESET,
+            manifest.version,
+            self.config.install_path.to_string_lossy()
         );
+        Ok(())
+    }
 
-        Ok(current_git_version)
+    /// Apply an update to the CLI given a new version, restarting into it
+    pub async fn apply_update(
+        &self,
+        new_version: &Version,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        self.install_update(new_version).await?;
+        crate::analytics::shutdown(ANALYTICS_FLUSH_TIMEOUT).await;
+        restart_cli_process_with_new_version(new_version, &self.current_version, &self.config)
     }
 
-    /// Get the latest release version of the CLI
-    fn get_cli_release_version(
+    /// Install and self-check a new version without restarting.
+    ///
+    /// The next start of the node runs the new version, which then has to confirm its health.
+    pub async fn install_update(
         &self,
-        should_write: bool,
-    ) -> Result<Version, Box<dyn std::error::Error>> {
-        let version = match self.config.mode {
-            AutoUpdaterMode::Test => {
-                let output = Command::new("git")
-                    .args(["describe", "--tags", "--abbrev=0"])
-                    .current_dir(&self.config.repo_path)
-                    .output()?;
-                Version::parse(String::from_utf8(output.stdout)?.trim())?
-            }
-            AutoUpdaterMode::Production => {
-                // Get only version tags (X.Y.Z format) from remote
-                // This filters out non-release tags using git's pattern matching
-                // Example matches: "1.2.3", "0.3.5"
-                // Won't match: "latest", "stable", or other non-version tags
-                let output = Command::new("git")
-                    .args([
-                        "ls-remote",
-                        "--refs",
-                        &self.config.remote_repo,
-                        "refs/tags/[0-9]*.[0-9]*.[0-9]*", // Only match semantic version tags
-                    ])
-                    .output()?;
-
-                let tags = String::from_utf8(output.stdout)?;
-
-                // Process the version tags:
-                // 1. Split each line and get the tag name
-                // 2. Parse into semver Version type (validates format)
-                // 3. Find the highest version number
-                tags.lines()
-                    .filter_map(|line| line.split('/').last())
-                    .filter_map(|tag| Version::parse(tag).ok())
-                    .max()
-                    .ok_or("No release versions found")?
-            }
-        };
+        new_version: &Version,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let manifest = self.fetch_manifest().await?;
+        if manifest.version != *new_version {
+            return Err(format!(
+                "Release manifest now points to {} instead of {}",
+                manifest.version, new_version
+            )
+            .into());
+        }
 
-        // Optionally persist the version to disk
-        if should_write {
-            write_version_to_file(&version)?;
+        self.download_and_install(&manifest).await?;
+
+        if let Err(reason) = self_check(&self.config.install_path).await {
             println!(
-                "{}[auto-updater]{} Wrote version to file: {}",
-                BLUE, RESET, version
+                "{}[auto-updater]{} Version {} failed its self-check ({}); keeping version {}",
+                BLUE,
+                RESET,
+                new_version,
+                reason,
+                self.current_version()
+            );
+            restore_previous_binary(&self.config.install_path)?;
+            write_skipped_version(new_version)?;
+            return Err(
+                format!("Version {} failed its self-check: {}", new_version, reason).into(),
             );
         }
 
-        Ok(version)
+        write_pending_update(&PendingUpdate {
+            previous_version: self.current_version(),
+            new_version: new_version.clone(),
+            install_path: self.config.install_path.clone(),
+            deadline: None,
+        })?;
+        Ok(())
     }
 
-    /// Apply an update to the CLI given a new version
-    pub fn apply_update(&self, new_version: &Version) -> Result<(), Box<dyn std::error::Error>> {
+    /// update the version status of the CLI. is there an update available?
+    pub async fn update_version_status(&self) -> Result<VersionStatus, Box<dyn std::error::Error>> {
+        let this_repo_version = self.current_version.read().clone();
+
+        // debug output
         println!(
-            "{}[auto-updater]{} Using repo path: {}",
-            BLUE, RESET, self.config.repo_path
+            "{}[auto-updater]{} Checking for updates from: {}",
+            BLUE, RESET, self.config.manifest_url
         );
 
-        let repo_path = std::path::Path::new(&self.config.repo_path);
-        if !repo_path.exists() {
-            return Err(format!("Repository not found at: {}", self.config.repo_path).into());
-        }
+        let latest_version = self
+            .fetch_manifest()
+            .await
+            .map_err(|e| format!("Version check failed: {}", e))?
+            .version;
 
-        match self.config.mode {
-            AutoUpdaterMode::Test => {
+        println!(
+            "{}[auto-updater]{} Current version of CLI: {} | Latest version of CLI: {}",
+            BLUE, RESET, this_repo_version, latest_version
+        );
+
+        if read_skipped_version().as_ref() == Some(&latest_version) {
+            println!(
+                "{}[auto-updater]{} Skipping version {}, which failed its health checks",
+                BLUE, RESET, latest_version
+            );
+            return Ok(VersionStatus::UpToDate);
+        }
+        match &self.config.pinned_version {
+            // Whatever the manifest's source, only the pinned version is installed
+            Some(pinned) if latest_version != *pinned => {
                 println!(
-                    "{}[auto-updater]{} Building version {} from local repository...",
-                    BLUE, RESET, new_version
+                    "{}[auto-updater]{} Ignoring version {}, as this node is pinned to {}",
+                    BLUE, RESET, latest_version, pinned
                 );
-                let build_output = Command::new("cargo")
-                    .args(["build", "--release"])
-                    .current_dir(repo_path)
-                    .output()?;
-
-                if !build_output.status.success() {
-                    return Err(format!(
-                        "Build failed: {}",
-                        String::from_utf8_lossy(&build_output.stderr)
-                    )
-                    .into());
-                }
+                Ok(VersionStatus::UpToDate)
             }
-            AutoUpdaterMode::Production => {
-                if repo_path.read_dir()?.next().is_none() {
-                    println!(
-                        "{}[auto-updater]{} Cloning remote repository...",
-                        BLUE, RESET
-                    );
-                    Command::new("git")
-                        .args(["clone", &self.config.remote_repo, &self.config.repo_path])
-                        .output()?;
-                }
+            // A pinned version is followed in either direction
+            Some(_) if latest_version != this_repo_version => {
+                Ok(VersionStatus::UpdateAvailable(latest_version))
+            }
+            // Otherwise releases never downgrade the node
+            None if latest_version > this_repo_version => {
+                Ok(VersionStatus::UpdateAvailable(latest_version))
+            }
+            _ => Ok(VersionStatus::UpToDate),
+        }
+    }
 
-                println!("{}[auto-updater]{} Fetching updates...", BLUE, RESET);
-                Command::new("git")
-                    .args(["fetch", "--all", "--tags", "--prune"])
-                    .current_dir(repo_path)
-                    .output()?;
+    /// Check for new releases every `update_interval` seconds and act on them per the policy
+    pub async fn run(&self) {
+        let mut policy = self.config.policy;
+        if policy == UpdatePolicy::Auto && self.config.public_key.is_none() {
+            println!(
+                "{}[auto-updater]{} This build has no pinned release key; new versions will only be reported",
+                BLUE, RESET
+            );
+            policy = UpdatePolicy::NotifyOnly;
+        }
 
-                println!(
-                    "{}[auto-updater]{} Checking out version {}...",
-                    BLUE, RESET, new_version
-                );
-                let checkout_output = Command::new("git")
-                    .args(["checkout", &format!("tags/{}", new_version)])
-                    .current_dir(repo_path)
-                    .output()?;
-
-                if !checkout_output.status.success() {
-                    return Err(format!(
-                        "Failed to checkout version: {}",
-                        String::from_utf8_lossy(&checkout_output.stderr)
-                    )
-                    .into());
+        let mut notified: Option<Version> = None;
+        loop {
+            // A failed check is retried at the next interval
+            let status = match self.update_version_status().await {
+                Ok(status) => status,
+                Err(e) => {
+                    println!("{}[auto-updater]{} {}", BLUE, RESET, e);
+                    VersionStatus::UpToDate
                 }
-
-                println!(
-                    "{}[auto-updater]{} Building version {} from remote repository...",
-                    BLUE, RESET, new_version
-                );
-                let build_output = Command::new("cargo")
-                    .args(["build", "--release"])
-                    .current_dir(repo_path)
-                    .output()?;
-
-                if !build_output.status.success() {
-                    return Err(format!(
-                        "Build failed: {}",
-                        String::from_utf8_lossy(&build_output.stderr)
-                    )
-                    .into());
+            };
+            if let VersionStatus::UpdateAvailable(new_version) = status {
+                match policy {
+                    UpdatePolicy::Auto => {
+                        if let Err(e) = self.apply_update(&new_version).await {
+                            println!(
+                                "{}[auto-updater]{} Failed to update to {}: {}",
+                                BLUE, RESET, new_version, e
+                            );
+                        }
+                    }
+                    UpdatePolicy::NotifyOnly if notified.as_ref() != Some(&new_version) => {
+                        println!(
+                            "{}[auto-updater]{} Version {} is available. Run `nexus-network update apply` to install it.",
+                            BLUE, RESET, new_version
+                        );
+                        notified = Some(new_version);
+                    }
+                    UpdatePolicy::NotifyOnly | UpdatePolicy::Disabled => {}
                 }
             }
+
+            tokio::time::sleep(Duration::from_secs(self.config.update_interval)).await;
         }
+    }
+}
 
-        restart_cli_process_with_new_version(new_version, &self.current_version, &self.config)
+/// Start checking for updates in the background, unless the policy disables it.
+///
+/// Must be called from within a tokio runtime.
+pub fn spawn_background_updater(config: UpdaterConfig) -> Result<(), Box<dyn std::error::Error>> {
+    if config.policy == UpdatePolicy::Disabled {
+        return Ok(());
     }
+    let manager = VersionManager::new(config)?;
+    tokio::spawn(async move { manager.run().await });
+    Ok(())
+}
 
-    /// update the version status of the CLI. is there an update available?
-    pub fn update_version_status(&self) -> Result<VersionStatus, Box<dyn std::error::Error>> {
-        let this_repo_version = self.current_version.read().clone();
+/// Report a rejected update on stderr and in `~/.nexus/security.log`
+fn log_security_event(message: &str) {
+    eprintln!("{}[auto-updater]{} SECURITY: {}", BLUE, RESET, message);
 
-        // debug output
-        println!(
-            "{}[auto-updater]{} Checking for updates from: {}",
-            BLUE, RESET, self.config.remote_repo
-        );
+    let Some(home) = home::home_dir() else {
+        return;
+    };
+    let line = format!("{} {}", chrono::Utc::now().to_rfc3339(), message);
+    let result = fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(home.join(".nexus").join("security.log"))
+        .and_then(|mut file| writeln!(file, "{}", line));
+    if let Err(e) = result {
+        eprintln!("Failed to write security log: {}", e);
+    }
+}
 
-        let latest_version = match self.get_cli_release_version(false) {
-            Ok(version) => version,
-            Err(e) => {
-                println!(
-                    "{}[auto-updater]{} Version check failed: {}",
-                    BLUE, RESET, e
-                );
-                return Ok(VersionStatus::UpToDate); // Gracefully handle error by assuming up-to-date
+fn staging_path(install_path: &Path) -> PathBuf {
+    let mut file_name = install_path.file_name().unwrap_or_default().to_os_string();
+    file_name.push(format!(".download-{}", std::process::id()));
+    install_path.with_file_name(file_name)
+}
+
+#[cfg(unix)]
+fn make_executable(path: &Path) -> std::io::Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+    fs::set_permissions(path, fs::Permissions::from_mode(0o755))
+}
+
+#[cfg(not(unix))]
+fn make_executable(_path: &Path) -> std::io::Result<()> {
+    Ok(())
+}
+
+/// Where the binary being replaced is kept in case the update has to be rolled back
+pub fn previous_binary_path(install_path: &Path) -> PathBuf {
+    let mut file_name = install_path.file_name().unwrap_or_default().to_os_string();
+    file_name.push(".previous");
+    install_path.with_file_name(file_name)
+}
+
+/// Move `target` aside to `previous`, then move `new_binary` into its place.
+///
+/// Renaming works even while `target` is running, including on Windows.
+fn swap_binary(new_binary: &Path, target: &Path, previous: &Path) -> std::io::Result<()> {
+    let _ = fs::remove_file(previous);
+    fs::rename(target, previous)?;
+    if let Err(e) = fs::rename(new_binary, target) {
+        let _ = fs::rename(previous, target);
+        return Err(e);
+    }
+    Ok(())
+}
+
+/// Put the previous binary back in place of the installed one
+fn restore_previous_binary(install_path: &Path) -> std::io::Result<()> {
+    let mut file_name = install_path.file_name().unwrap_or_default().to_os_string();
+    file_name.push(".failed");
+    let failed = install_path.with_file_name(file_name);
+
+    let _ = fs::remove_file(&failed);
+    fs::rename(install_path, &failed)?;
+    fs::rename(previous_binary_path(install_path), install_path)
+}
+
+/// Run `binary self-check`, which proves a small program and exits
+async fn self_check(binary: &Path) -> Result<(), String> {
+    let mut child = tokio::process::Command::new(binary)
+        .arg("self-check")
+        .stdout(Stdio::null())
+        .kill_on_drop(true)
+        .spawn()
+        .map_err(|e| format!("could not start: {}", e))?;
+
+    match tokio::time::timeout(SELF_CHECK_TIMEOUT, child.wait()).await {
+        Ok(Ok(status)) if status.success() => Ok(()),
+        Ok(Ok(status)) => Err(format!("exited with {}", status)),
+        Ok(Err(e)) => Err(e.to_string()),
+        Err(_) => Err(format!(
+            "did not finish within {} seconds",
+            SELF_CHECK_TIMEOUT.as_secs()
+        )),
+    }
+}
+
+fn unix_time() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+fn read_pending_update() -> Option<PendingUpdate> {
+    let content = fs::read_to_string(state::path(state::UPDATE_PENDING_FILE)).ok()?;
+    serde_json::from_str(&content).ok()
+}
+
+fn write_pending_update(pending: &PendingUpdate) -> Result<(), Box<dyn std::error::Error>> {
+    fs::write(
+        state::path(state::UPDATE_PENDING_FILE),
+        serde_json::to_string_pretty(pending)?,
+    )?;
+    Ok(())
+}
+
+fn read_skipped_version() -> Option<Version> {
+    let content = fs::read_to_string(state::path(state::SKIPPED_VERSION_FILE)).ok()?;
+    Version::parse(content.trim()).ok()
+}
+
+fn write_skipped_version(version: &Version) -> Result<(), Box<dyn std::error::Error>> {
+    fs::write(
+        state::path(state::SKIPPED_VERSION_FILE),
+        version.to_string(),
+    )?;
+    Ok(())
+}
+
+/// Check whether this process is a freshly installed version that still has to prove itself.
+///
+/// Call once at startup, from within a tokio runtime. The new version has until a deadline,
+/// counted from its first start, to confirm its health; restarting it does not move the
+/// deadline. If the deadline has passed, the update is rolled back right away. Otherwise a
+/// watchdog rolls it back unless `confirm_update_health` is called before the deadline.
+///
+/// Fails if a rollback was due but did not go through; the node must not keep running the
+/// failed version, and the marker is left in place so the next start tries again.
+pub fn check_pending_update() -> Result<(), Box<dyn std::error::Error>> {
+    let Some(pending) = read_pending_update() else {
+        return Ok(());
+    };
+
+    // The marker belongs to a different binary, e.g. one that was replaced by hand
+    if pending.new_version.to_string() != env!("CARGO_PKG_VERSION") {
+        let _ = fs::remove_file(state::path(state::UPDATE_PENDING_FILE));
+        return Ok(());
+    }
+
+    let deadline = match pending.deadline {
+        Some(deadline) if unix_time() >= deadline => {
+            rollback_pending_update(&pending, "it did not confirm its health in time")?;
+            deadline
+        }
+        Some(deadline) => deadline,
+        None => {
+            let deadline = unix_time() + HEALTH_CHECK_DEADLINE.as_secs();
+            let pending = PendingUpdate {
+                deadline: Some(deadline),
+                ..pending.clone()
+            };
+            if let Err(e) = write_pending_update(&pending) {
+                eprintln!("Failed to update the pending update marker: {}", e);
             }
-        };
+            deadline
+        }
+    };
 
-        println!(
-            "{}[auto-updater]{} Current version of CLI: {} | Latest version of CLI: {}",
-            BLUE, RESET, this_repo_version, latest_version
-        );
+    println!(
+        "{}[auto-updater]{} Running new version {}; waiting for it to complete a proof",
+        BLUE, RESET, pending.new_version
+    );
 
-        if this_repo_version == latest_version {
-            Ok(VersionStatus::UpToDate)
-        } else {
-            Ok(VersionStatus::UpdateAvailable(latest_version))
+    tokio::spawn(async move {
+        tokio::time::sleep(Duration::from_secs(deadline.saturating_sub(unix_time()))).await;
+        if read_pending_update().is_some() {
+            // The previous binary replaces this process; events not sent by then are lost
+            crate::analytics::shutdown(ANALYTICS_FLUSH_TIMEOUT).await;
+            if let Err(e) =
+                rollback_pending_update(&pending, "it did not confirm its health in time")
+            {
+                eprintln!("{}[auto-updater]{} Rollback failed: {}", BLUE, RESET, e);
+                std::process::exit(1);
+            }
         }
+    });
+    Ok(())
+}
+
+/// Mark a freshly installed version as healthy, cancelling any pending rollback
+pub fn confirm_update_health() {
+    let Some(pending) = read_pending_update() else {
+        return;
+    };
+    if fs::remove_file(state::path(state::UPDATE_PENDING_FILE)).is_ok() {
+        println!(
+            "{}[auto-updater]{} Version {} is healthy; update complete",
+            BLUE, RESET, pending.new_version
+        );
     }
 }
 
-/// function to read the current git tag version from a file
-pub fn read_version_from_file() -> Result<Version, Box<dyn std::error::Error>> {
-    let version_str = fs::read_to_string(VERSION_FILE)?;
-    Ok(Version::parse(&version_str)?)
+/// Restore the previous binary and restart it. Only returns if that fails.
+///
+/// The pending update marker is only removed once the previous binary is back in place.
+fn rollback_pending_update(
+    pending: &PendingUpdate,
+    reason: &str,
+) -> Result<(), Box<dyn std::error::Error>> {
+    println!(
+        "{}[auto-updater]{} Rolling back from {} to {} because {}",
+        BLUE, RESET, pending.new_version, pending.previous_version, reason
+    );
+
+    restore_previous_binary(&pending.install_path)?;
+    write_skipped_version(&pending.new_version)?;
+    write_version_to_file(&pending.previous_version)?;
+    fs::remove_file(state::path(state::UPDATE_PENDING_FILE))?;
+    restart_with(&pending.install_path)
 }
 
-/// function to write the current git tag version to a file so it can be read by the updater thread
+/// function to write the current version to a file so it can be read by external tooling
 /// We write to a file because storing the version in memory is not persistent across updates
 pub fn write_version_to_file(version: &Version) -> Result<(), Box<dyn std::error::Error>> {
-    fs::write(VERSION_FILE, version.to_string())?;
+    fs::write(state::path(state::VERSION_FILE), version.to_string())?;
     Ok(())
 }
 
 /// Restart the CLI process with a new version
+///
+/// The new binary is started with the same arguments as this process.
 pub fn restart_cli_process_with_new_version(
     new_version: &Version,
     current_version: &Arc<RwLock<Version>>,
@@ -306,39 +773,311 @@ pub fn restart_cli_process_with_new_version(
     *current_version.write() = new_version.clone();
     write_version_to_file(new_version)?;
 
-    let cli_path = std::path::Path::new(&config.repo_path);
-
-    let mode_arg = match config.mode {
-        AutoUpdaterMode::Test => "test",
-        AutoUpdaterMode::Production => "production",
-    };
-
-    let child = Command::new("cargo")
-        .args([
-            "run",
-            "--release",
-            "--",
-            &config.hostname,
-            "--updater-mode",
-            mode_arg,
-        ])
-        .current_dir(cli_path)
-        .process_group(0)
-        .spawn()?;
-
-    // Write the new PID to a file
-    std::fs::write(".prover.pid", child.id().to_string())?;
-
-    println!(
-        "{}[auto-updater]{} Started new process with PID: {}",
-        BLUE,
-        RESET,
-        child.id()
-    );
     println!(
         "{}[auto-updater]{} Restarting with new version...",
         BLUE, RESET
     );
 
-    std::process::exit(0);
+    restart_with(&config.install_path)
+}
+
+/// Replace this process with `binary`, passing along this process's arguments
+fn restart_with(binary: &Path) -> Result<(), Box<dyn std::error::Error>> {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+
+    // On Unix the new binary replaces this process image and keeps its PID
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::CommandExt;
+
+        fs::write(state::path(state::PID_FILE), std::process::id().to_string())?;
+        let error = Command::new(binary).args(&args).exec();
+        Err(format!("Failed to start {}: {}", binary.to_string_lossy(), error).into())
+    }
+
+    #[cfg(not(unix))]
+    {
+        let child = Command::new(binary).args(&args).spawn()?;
+
+        // Write the new PID to a file
+        fs::write(state::path(state::PID_FILE), child.id().to_string())?;
+
+        println!(
+            "{}[auto-updater]{} Started new process with PID: {}",
+            BLUE,
+            RESET,
+            child.id()
+        );
+
+        std::process::exit(0);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ed25519_dalek::{Signer, SigningKey};
+    use serde_json::json;
+    use std::sync::Mutex;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    /// Minimal HTTP server that serves fixed bodies by path
+    struct ManifestServer {
+        base_url: String,
+        routes: Arc<Mutex<HashMap<String, Vec<u8>>>>,
+    }
+
+    impl ManifestServer {
+        async fn start() -> Self {
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let base_url = format!("http://{}", listener.local_addr().unwrap());
+            let routes: Arc<Mutex<HashMap<String, Vec<u8>>>> = Arc::default();
+
+            let served = routes.clone();
+            tokio::spawn(async move {
+                while let Ok((mut stream, _)) = listener.accept().await {
+                    let mut request = vec![0u8; 4096];
+                    let n = stream.read(&mut request).await.unwrap_or(0);
+                    let request = String::from_utf8_lossy(&request[..n]).to_string();
+                    let path = request.split_whitespace().nth(1).unwrap_or("/");
+
+                    let body = served.lock().unwrap().get(path).cloned();
+                    let response = match body {
+                        Some(body) => [
+                            format!(
+                                "HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+                                body.len()
+                            )
+                            .into_bytes(),
+                            body,
+                        ]
+                        .concat(),
+                        None => b"HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n"
+                            .to_vec(),
+                    };
+                    let _ = stream.write_all(&response).await;
+                }
+            });
+
+            Self { base_url, routes }
+        }
+
+        fn serve(&self, path: &str, body: Vec<u8>) {
+            self.routes.lock().unwrap().insert(path.to_string(), body);
+        }
+
+        fn publish(&self, version: &str, binary: &[u8]) {
+            self.publish_signed(version, binary, binary);
+        }
+
+        /// Publish `binary` with a checksum and signature computed over `signed`
+        fn publish_signed(&self, version: &str, binary: &[u8], signed: &[u8]) {
+            self.serve("/nexus-network", binary.to_vec());
+            let sha256 = format!("{:x}", Sha256::digest(signed));
+            let message = signed_message(&Version::parse(version).unwrap(), &sha256);
+            let manifest = json!({
+                "version": version,
+                "artifacts": {
+                    platform_key(): {
+                        "url": format!("{}/nexus-network", self.base_url),
+                        "sha256": sha256,
+                        "signature": hex::encode(signing_key().sign(message.as_bytes()).to_bytes()),
+                    },
+                },
+            });
+            self.serve("/manifest.json", manifest.to_string().into_bytes());
+        }
+    }
+
+    fn signing_key() -> SigningKey {
+        SigningKey::from_bytes(&[7; 32])
+    }
+
+    fn test_config(server: &ManifestServer, install_path: PathBuf) -> UpdaterConfig {
+        UpdaterConfig {
+            update_interval: 30,
+            policy: UpdatePolicy::Auto,
+            pinned_version: None,
+            manifest_url: format!("{}/manifest.json", server.base_url),
+            install_path,
+            public_key: Some(signing_key().verifying_key()),
+            network: NetworkSettings::default(),
+        }
+    }
+
+    fn temp_install_path(name: &str) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("nexus-updater-{}-{}", name, std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        dir.join("nexus-network")
+    }
+
+    #[tokio::test]
+    async fn test_newer_manifest_version_is_an_update() {
+        let server = ManifestServer::start().await;
+        server.publish("99.0.0", b"new binary");
+        let manager =
+            VersionManager::new(test_config(&server, temp_install_path("status"))).unwrap();
+
+        match manager.update_version_status().await.unwrap() {
+            VersionStatus::UpdateAvailable(version) => {
+                assert_eq!(version, Version::new(99, 0, 0))
+            }
+            VersionStatus::UpToDate => panic!("expected an update"),
+        }
+
+        for version in [env!("CARGO_PKG_VERSION"), "0.0.1"] {
+            server.publish(version, b"same or older binary");
+            assert!(matches!(
+                manager.update_version_status().await.unwrap(),
+                VersionStatus::UpToDate
+            ));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_failed_version_check_is_an_error() {
+        // Nothing is published, so the manifest is not found
+        let server = ManifestServer::start().await;
+        let manager =
+            VersionManager::new(test_config(&server, temp_install_path("failed"))).unwrap();
+
+        assert!(manager.update_version_status().await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_pinned_older_version_is_an_update() {
+        let server = ManifestServer::start().await;
+        server.publish("0.0.1", b"old release");
+        let mut config = test_config(&server, temp_install_path("pinned"));
+        config.pinned_version = Some(Version::new(0, 0, 1));
+        let manager = VersionManager::new(config).unwrap();
+
+        assert!(matches!(
+            manager.update_version_status().await.unwrap(),
+            VersionStatus::UpdateAvailable(version) if version == Version::new(0, 0, 1)
+        ));
+
+        // As from a mirror that serves another release
+        server.publish("99.0.0", b"unpinned release");
+        assert!(matches!(
+            manager.update_version_status().await.unwrap(),
+            VersionStatus::UpToDate
+        ));
+    }
+
+    #[test]
+    fn test_manifest_url_follows_channel_and_pin() {
+        let mut settings = UpdaterSettings::default();
+        assert_eq!(settings.manifest_url(), DEFAULT_MANIFEST_URL);
+
+        settings.channel = UpdateChannel::Beta;
+        assert_eq!(settings.manifest_url(), BETA_MANIFEST_URL);
+
+        settings.pinned_version = Some(Version::new(0, 8, 1));
+        assert_eq!(
+            settings.manifest_url(),
+            "https://github.com/nexus-xyz/network-api/releases/download/v0.8.1/manifest.json"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_download_and_install_replaces_binary() {
+        let server = ManifestServer::start().await;
+        server.publish("99.0.0", b"new binary");
+        let install_path = temp_install_path("install");
+        fs::write(&install_path, b"old binary").unwrap();
+        let manager = VersionManager::new(test_config(&server, install_path.clone())).unwrap();
+
+        let manifest = manager.fetch_manifest().await.unwrap();
+        manager.download_and_install(&manifest).await.unwrap();
+
+        assert_eq!(fs::read(&install_path).unwrap(), b"new binary");
+        assert_eq!(
+            fs::read(previous_binary_path(&install_path)).unwrap(),
+            b"old binary"
+        );
+        assert!(!staging_path(&install_path).exists());
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = fs::metadata(&install_path).unwrap().permissions().mode();
+            assert_eq!(mode & 0o111, 0o111);
+        }
+        fs::remove_dir_all(install_path.parent().unwrap()).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_tampered_binary_is_refused() {
+        let server = ManifestServer::start().await;
+        server.publish_signed("99.0.0", b"malicious binary", b"new binary");
+        let install_path = temp_install_path("tampered");
+        fs::write(&install_path, b"old binary").unwrap();
+        let manager = VersionManager::new(test_config(&server, install_path.clone())).unwrap();
+
+        let manifest = manager.fetch_manifest().await.unwrap();
+        assert!(manager.download_and_install(&manifest).await.is_err());
+        assert_eq!(fs::read(&install_path).unwrap(), b"old binary");
+        fs::remove_dir_all(install_path.parent().unwrap()).unwrap();
+    }
+
+    fn signed_artifact(version: &Version, binary: &[u8], key: &SigningKey) -> ReleaseArtifact {
+        let sha256 = format!("{:x}", Sha256::digest(binary));
+        let signature = key.sign(signed_message(version, &sha256).as_bytes());
+        ReleaseArtifact {
+            url: String::new(),
+            sha256,
+            signature: hex::encode(signature.to_bytes()),
+        }
+    }
+
+    #[test]
+    fn test_signature_from_another_key_is_rejected() {
+        let binary = b"new binary";
+        let version = Version::new(99, 0, 0);
+        let other_key = SigningKey::from_bytes(&[8; 32]);
+        let artifact = signed_artifact(&version, binary, &other_key);
+
+        assert!(artifact
+            .verify(&version, binary, &signing_key().verifying_key())
+            .is_err());
+        assert!(artifact
+            .verify(&version, binary, &other_key.verifying_key())
+            .is_ok());
+    }
+
+    #[test]
+    fn test_older_release_cannot_be_replayed_as_newer() {
+        let binary = b"old binary";
+        let artifact = signed_artifact(&Version::new(0, 1, 0), binary, &signing_key());
+
+        let error = artifact
+            .verify(
+                &Version::new(99, 0, 0),
+                binary,
+                &signing_key().verifying_key(),
+            )
+            .unwrap_err();
+        assert!(error.contains("99.0.0"), "{}", error);
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_failed_self_check_restores_previous_binary() {
+        let server = ManifestServer::start().await;
+        server.publish("99.0.0", b"#!/bin/sh\nexit 1\n");
+        let install_path = temp_install_path("rollback");
+        fs::write(&install_path, b"old binary").unwrap();
+        let manager = VersionManager::new(test_config(&server, install_path.clone())).unwrap();
+
+        let manifest = manager.fetch_manifest().await.unwrap();
+        manager.download_and_install(&manifest).await.unwrap();
+        assert!(self_check(&install_path).await.is_err());
+
+        restore_previous_binary(&install_path).unwrap();
+        assert_eq!(fs::read(&install_path).unwrap(), b"old binary");
+        assert!(!previous_binary_path(&install_path).exists());
+        fs::remove_dir_all(install_path.parent().unwrap()).unwrap();
+    }
 }